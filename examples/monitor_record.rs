@@ -17,6 +17,13 @@ fn main() {
         }
     });
 
+    let events = video_recorder.events().unwrap();
+    thread::spawn(move || {
+        for event in events {
+            println!("event: {:?}", event);
+        }
+    });
+
     println!("start");
     video_recorder.start().unwrap();
     thread::sleep(Duration::from_secs(2));
@@ -28,4 +35,6 @@ fn main() {
     thread::sleep(Duration::from_secs(2));
     println!("stop");
     video_recorder.stop().unwrap();
+    println!("close {:?}", video_recorder.state().unwrap());
+    video_recorder.close().unwrap();
 }
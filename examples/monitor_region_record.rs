@@ -0,0 +1,21 @@
+use std::{thread, time::Duration};
+use xcap::Monitor;
+
+fn main() {
+    let monitor = Monitor::from_point(100, 100).unwrap();
+
+    let (video_recorder, sx) = monitor.region_video_recorder(0, 0, 400, 300).unwrap();
+
+    thread::spawn(move || {
+        for frame in sx {
+            println!(
+                "frame: {}x{} {:?}",
+                frame.width, frame.height, frame.dirty_rects
+            );
+        }
+    });
+
+    video_recorder.start().unwrap();
+    thread::sleep(Duration::from_secs(5));
+    video_recorder.close().unwrap();
+}
@@ -0,0 +1,25 @@
+use std::time::Duration;
+use xcap::{DirtyRect, Monitor};
+
+fn main() {
+    let monitor = Monitor::from_point(100, 100).unwrap();
+
+    let region = DirtyRect {
+        x: 0,
+        y: 0,
+        width: monitor.width().unwrap() / 2,
+        height: monitor.height().unwrap() / 2,
+    };
+
+    println!("Waiting for the top left quarter to change...");
+    let image = monitor
+        .wait_for_change(Some(region), Duration::from_secs(30))
+        .unwrap();
+    image.save("target/monitor_wait_change.png").unwrap();
+
+    println!("Waiting for the screen to settle...");
+    let image = monitor
+        .wait_until_stable(None, Duration::from_millis(500), Duration::from_secs(30))
+        .unwrap();
+    image.save("target/monitor_wait_stable.png").unwrap();
+}
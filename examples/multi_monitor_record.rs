@@ -0,0 +1,19 @@
+use std::{thread, time::Duration};
+use xcap::MultiMonitorRecorder;
+
+fn main() {
+    let (video_recorder, sx) = MultiMonitorRecorder::all().unwrap();
+
+    thread::spawn(move || {
+        for frame in sx {
+            println!(
+                "frame: {}x{} {:?}",
+                frame.width, frame.height, frame.timestamp
+            );
+        }
+    });
+
+    video_recorder.start().unwrap();
+    thread::sleep(Duration::from_secs(5));
+    video_recorder.close().unwrap();
+}
@@ -0,0 +1,25 @@
+use std::{fs::File, thread, time::Duration};
+use xcap::{AviWriter, GifWriter, Monitor, Pipeline, ScaleSink};
+
+fn main() {
+    let monitor = Monitor::from_point(100, 100).unwrap();
+
+    let (video_recorder, sx) = monitor.video_recorder().unwrap();
+
+    let gif = GifWriter::new(File::create("target/pipeline_record.gif").unwrap()).unwrap();
+    let avi = AviWriter::new(File::create("target/pipeline_record.avi").unwrap()).unwrap();
+
+    let pipeline = Pipeline::new()
+        .sink(ScaleSink::new(640, 360, gif))
+        .sink(avi)
+        .run(&video_recorder, sx)
+        .unwrap();
+
+    video_recorder.start().unwrap();
+    thread::sleep(Duration::from_secs(5));
+    video_recorder.close().unwrap();
+    drop(video_recorder);
+
+    println!("dropped frames: {:?}", pipeline.dropped_frames());
+    pipeline.join().unwrap();
+}
@@ -0,0 +1,37 @@
+use std::{fs::File, thread, time::Duration};
+use xcap::{GifWriter, Monitor, Pipeline, ReplayBuffer, ReplayCompression, ReplayOptions};
+
+fn main() {
+    let monitor = Monitor::from_point(100, 100).unwrap();
+
+    let (video_recorder, sx) = monitor.video_recorder().unwrap();
+
+    let replay = ReplayBuffer::new(&ReplayOptions {
+        max_duration: Duration::from_secs(10),
+        compression: ReplayCompression::Jpeg(80),
+        ..Default::default()
+    })
+    .unwrap();
+
+    let pipeline = Pipeline::new()
+        .sink(replay.clone())
+        .run(&video_recorder, sx)
+        .unwrap();
+
+    video_recorder.start().unwrap();
+    thread::sleep(Duration::from_secs(15));
+
+    // 保存最近 5 秒
+    let frames = replay.dump(Duration::from_secs(5)).unwrap();
+    println!("replay frames: {}", frames.len());
+
+    let mut gif = GifWriter::new(File::create("target/replay_buffer.gif").unwrap()).unwrap();
+    for frame in &frames {
+        gif.write_frame(frame).unwrap();
+    }
+    gif.finish().unwrap();
+
+    video_recorder.close().unwrap();
+    drop(video_recorder);
+    pipeline.join().unwrap();
+}
@@ -89,6 +89,9 @@ fn main() {
                     println!("   Difference: {}x{}", 
                         image_width as i32 - window_width as i32,
                         image_height as i32 - window_height as i32);
+                    if let Ok(frame_extents) = window.frame_extents() {
+                        println!("   Frame extents: {:?}", frame_extents);
+                    }
                     
                     // Save all mismatch examples for analysis
                     let filename = format!(
@@ -0,0 +1,24 @@
+use std::{thread, time::Duration};
+use xcap::Window;
+
+fn main() {
+    let windows = Window::all().unwrap();
+    let window = windows
+        .iter()
+        .find(|window| !window.is_minimized().unwrap_or(true))
+        .unwrap();
+
+    println!("record: {}", window.title().unwrap());
+
+    let (video_recorder, sx) = window.video_recorder().unwrap();
+
+    thread::spawn(move || {
+        for frame in sx {
+            println!("frame: {}x{}", frame.width, frame.height);
+        }
+    });
+
+    video_recorder.start().unwrap();
+    thread::sleep(Duration::from_secs(5));
+    video_recorder.close().unwrap();
+}
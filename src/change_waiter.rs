@@ -0,0 +1,47 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::error::XCapResult;
+
+/// How often [`PollingWaiter`] lets the caller capture again by default
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Blocks until a region of the screen may have changed.
+///
+/// Waiters may wake up without a change, callers compare captures to be sure.
+pub(crate) trait ChangeWaiter {
+    /// Return once the region may have changed, or after `timeout`.
+    fn wait(&mut self, timeout: Duration) -> XCapResult<()>;
+
+    /// The clock deadlines are measured against.
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// For platforms without change notifications, wakes up at a fixed interval.
+pub(crate) struct PollingWaiter {
+    interval: Duration,
+}
+
+impl PollingWaiter {
+    pub fn new(interval: Duration) -> PollingWaiter {
+        PollingWaiter { interval }
+    }
+}
+
+impl Default for PollingWaiter {
+    fn default() -> Self {
+        PollingWaiter::new(POLL_INTERVAL)
+    }
+}
+
+impl ChangeWaiter for PollingWaiter {
+    fn wait(&mut self, timeout: Duration) -> XCapResult<()> {
+        thread::sleep(timeout.min(self.interval));
+
+        Ok(())
+    }
+}
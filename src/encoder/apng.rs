@@ -0,0 +1,421 @@
+use std::{
+    io::{Seek, SeekFrom, Write},
+    time::Duration,
+};
+
+use png::{BitDepth, ColorType, Compression, Encoder};
+
+use crate::{
+    error::{XCapError, XCapResult},
+    video_recorder::{DirtyRect, Frame},
+};
+
+use super::FrameNormalizer;
+
+const PNG_SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+
+/// Options for [`ApngWriter::with_options`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApngOptions {
+    /// Compress faster at the cost of a larger file.
+    pub fast: bool,
+    /// Play the animation in a loop instead of once.
+    pub repeat: bool,
+    /// How long the last frame is shown.
+    pub last_frame_delay: Duration,
+}
+
+impl Default for ApngOptions {
+    fn default() -> Self {
+        ApngOptions {
+            fast: false,
+            repeat: true,
+            last_frame_delay: Duration::from_millis(100),
+        }
+    }
+}
+
+fn write_chunk<W: Write>(writer: &mut W, chunk_type: &[u8; 4], data: &[u8]) -> XCapResult<()> {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(chunk_type);
+    hasher.update(data);
+
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(chunk_type)?;
+    writer.write_all(data)?;
+    writer.write_all(&hasher.finalize().to_be_bytes())?;
+
+    Ok(())
+}
+
+/// The zlib stream of an RGBA image, taken from the IDAT chunks of a PNG
+fn compress(width: u32, height: u32, rgba: &[u8], compression: Compression) -> XCapResult<Vec<u8>> {
+    let mut png = Vec::new();
+    let mut encoder = Encoder::new(&mut png, width, height);
+    encoder.set_color(ColorType::Rgba);
+    encoder.set_depth(BitDepth::Eight);
+    encoder.set_compression(compression);
+
+    let mut writer = encoder.write_header().map_err(XCapError::new)?;
+    writer.write_image_data(rgba).map_err(XCapError::new)?;
+    writer.finish().map_err(XCapError::new)?;
+
+    let mut data = Vec::new();
+    let mut offset = PNG_SIGNATURE.len();
+    while offset + 8 <= png.len() {
+        let mut length = [0; 4];
+        length.copy_from_slice(&png[offset..offset + 4]);
+        let length = u32::from_be_bytes(length) as usize;
+
+        let chunk_data = png
+            .get(offset + 8..offset + 8 + length)
+            .ok_or_else(|| XCapError::new("Invalid PNG chunk"))?;
+        if &png[offset + 4..offset + 8] == b"IDAT" {
+            data.extend_from_slice(chunk_data);
+        }
+
+        // 长度、类型和 CRC 各占 4 字节
+        offset += length + 12;
+    }
+
+    Ok(data)
+}
+
+/// A changed area of the animation, in RGBA
+struct Patch {
+    rect: DirtyRect,
+    rgba: Vec<u8>,
+}
+
+/// Writes recorder frames as a lossless animated PNG.
+///
+/// Only the changed area of each frame is stored, looked up inside the frame's dirty rects
+/// (the whole frame after a gap in the frame sequence), and identical consecutive frames are
+/// merged. Frame delays come from the frame timestamps, the IHDR size from the first frame.
+/// The `acTL` frame count is only known at the end, [`ApngWriter::finish`] seeks back to it.
+pub struct ApngWriter<W: Write + Seek> {
+    writer: W,
+    options: ApngOptions,
+    frames: FrameNormalizer,
+    /// The animation as shown after the pending frame
+    canvas: Vec<u8>,
+    /// Where the acTL chunk starts
+    actl_position: u64,
+    sequence_number: u32,
+    num_frames: u32,
+    /// The sequence number of the last written frame
+    last_sequence: Option<u64>,
+    /// The frame waiting for the next one to know its delay, and its start in milliseconds
+    pending: Option<(Patch, u64)>,
+}
+
+impl<W: Write + Seek> ApngWriter<W> {
+    pub fn new(writer: W) -> XCapResult<Self> {
+        ApngWriter::with_options(writer, &ApngOptions::default())
+    }
+
+    pub fn with_options(writer: W, options: &ApngOptions) -> XCapResult<Self> {
+        Ok(ApngWriter {
+            writer,
+            options: options.clone(),
+            frames: FrameNormalizer::default(),
+            canvas: Vec::new(),
+            actl_position: 0,
+            sequence_number: 0,
+            num_frames: 0,
+            last_sequence: None,
+            pending: None,
+        })
+    }
+
+    fn write_actl(&mut self, num_frames: u32) -> XCapResult<()> {
+        let num_plays: u32 = if self.options.repeat { 0 } else { 1 };
+
+        let mut data = [0; 8];
+        data[..4].copy_from_slice(&num_frames.to_be_bytes());
+        data[4..].copy_from_slice(&num_plays.to_be_bytes());
+
+        write_chunk(&mut self.writer, b"acTL", &data)
+    }
+
+    fn write_header(&mut self, width: u32, height: u32) -> XCapResult<()> {
+        if width == 0 || height == 0 {
+            return Err(XCapError::new("APNG frames must not be empty"));
+        }
+
+        self.writer.write_all(&PNG_SIGNATURE)?;
+
+        // 8 位 RGBA，不隔行
+        let mut ihdr = [0; 13];
+        ihdr[..4].copy_from_slice(&width.to_be_bytes());
+        ihdr[4..8].copy_from_slice(&height.to_be_bytes());
+        ihdr[8] = 8;
+        ihdr[9] = 6;
+        write_chunk(&mut self.writer, b"IHDR", &ihdr)?;
+
+        // 帧数在 finish 时回写
+        self.actl_position = self.writer.stream_position()?;
+        self.write_actl(0)?;
+
+        self.frames.set_size(width, height);
+
+        Ok(())
+    }
+
+    fn write_patch(&mut self, patch: Patch, delay: u64) -> XCapResult<()> {
+        let delay = delay.clamp(1, u16::MAX as u64) as u16;
+
+        let mut fctl = [0; 26];
+        fctl[..4].copy_from_slice(&self.sequence_number.to_be_bytes());
+        fctl[4..8].copy_from_slice(&patch.rect.width.to_be_bytes());
+        fctl[8..12].copy_from_slice(&patch.rect.height.to_be_bytes());
+        fctl[12..16].copy_from_slice(&patch.rect.x.to_be_bytes());
+        fctl[16..20].copy_from_slice(&patch.rect.y.to_be_bytes());
+        fctl[20..22].copy_from_slice(&delay.to_be_bytes());
+        fctl[22..24].copy_from_slice(&1000u16.to_be_bytes());
+        // dispose_op 为 None，blend_op 为 Source，直接覆盖变化区域
+        write_chunk(&mut self.writer, b"fcTL", &fctl)?;
+        self.sequence_number += 1;
+
+        let compression = if self.options.fast {
+            Compression::Fast
+        } else {
+            Compression::Balanced
+        };
+        let data = compress(
+            patch.rect.width,
+            patch.rect.height,
+            &patch.rgba,
+            compression,
+        )?;
+
+        // 第一帧即默认图像，后续帧使用 fdAT
+        if self.num_frames == 0 {
+            write_chunk(&mut self.writer, b"IDAT", &data)?;
+        } else {
+            let mut fdat = Vec::with_capacity(data.len() + 4);
+            fdat.extend_from_slice(&self.sequence_number.to_be_bytes());
+            fdat.extend_from_slice(&data);
+            write_chunk(&mut self.writer, b"fdAT", &fdat)?;
+            self.sequence_number += 1;
+        }
+
+        self.num_frames += 1;
+
+        Ok(())
+    }
+
+    /// The bounding box of the pixels inside the dirty rects that differ from the canvas
+    fn changed_rect(&self, width: u32, raw: &[u8], dirty_rects: &[DirtyRect]) -> Option<DirtyRect> {
+        let bounds = DirtyRect {
+            x: 0,
+            y: 0,
+            width,
+            height: (raw.len() / (width as usize * 4)) as u32,
+        };
+
+        // 左、上、右、下边界，右和下不包含
+        let mut changed: Option<(u32, u32, u32, u32)> = None;
+        for dirty_rect in dirty_rects
+            .iter()
+            .filter_map(|dirty_rect| dirty_rect.intersect(&bounds))
+        {
+            for y in dirty_rect.y..dirty_rect.y + dirty_rect.height {
+                let start = (y as usize * width as usize + dirty_rect.x as usize) * 4;
+                let end = start + dirty_rect.width as usize * 4;
+                let (old, new) = (&self.canvas[start..end], &raw[start..end]);
+                if old == new {
+                    continue;
+                }
+
+                let differs = |i: &u32| {
+                    let i = *i as usize * 4;
+                    old[i..i + 4] != new[i..i + 4]
+                };
+                let left = (0..dirty_rect.width).find(differs).unwrap_or(0);
+                let right = (0..dirty_rect.width).rev().find(differs).unwrap_or(0);
+
+                let (left, right) = (dirty_rect.x + left, dirty_rect.x + right + 1);
+                changed = Some(match changed {
+                    Some((l, t, r, b)) => (l.min(left), t.min(y), r.max(right), b.max(y + 1)),
+                    None => (left, y, right, y + 1),
+                });
+            }
+        }
+
+        changed.map(|(left, top, right, bottom)| DirtyRect {
+            x: left,
+            y: top,
+            width: right - left,
+            height: bottom - top,
+        })
+    }
+
+    /// Copy the changed area into the canvas
+    fn take_patch(&mut self, width: u32, raw: &[u8], rect: DirtyRect) -> Patch {
+        let row_size = rect.width as usize * 4;
+        let mut rgba = Vec::with_capacity(row_size * rect.height as usize);
+        for y in rect.y..rect.y + rect.height {
+            let start = (y as usize * width as usize + rect.x as usize) * 4;
+            let row = &raw[start..start + row_size];
+            rgba.extend_from_slice(row);
+            self.canvas[start..start + row_size].copy_from_slice(row);
+        }
+
+        Patch { rect, rgba }
+    }
+
+    pub fn write_frame(&mut self, frame: &Frame) -> XCapResult<()> {
+        if self.frames.size().is_none() {
+            self.write_header(frame.width, frame.height)?;
+        }
+        let (frame, elapsed) = self.frames.normalize(frame)?;
+        let (width, height) = (frame.width, frame.height);
+        let raw = frame.to_rgba_image()?.into_raw();
+
+        // 中间有帧被丢弃时，脏区域不完整，需要比较整帧
+        let contiguous = self
+            .last_sequence
+            .is_some_and(|last| frame.sequence == last.wrapping_add(1));
+        self.last_sequence = Some(frame.sequence);
+        let dirty_rects = if contiguous {
+            frame.dirty_rects.clone()
+        } else {
+            vec![DirtyRect {
+                x: 0,
+                y: 0,
+                width,
+                height,
+            }]
+        };
+
+        let start = elapsed.as_millis() as u64;
+
+        let Some((pending, pending_start)) = self.pending.take() else {
+            let rect = DirtyRect {
+                x: 0,
+                y: 0,
+                width,
+                height,
+            };
+            self.pending = Some((
+                Patch {
+                    rect,
+                    rgba: raw.clone(),
+                },
+                start,
+            ));
+            self.canvas = raw;
+            return Ok(());
+        };
+
+        // 内容相同的帧合并为一帧，延长显示时间
+        let Some(rect) = self.changed_rect(width, &raw, &dirty_rects) else {
+            self.pending = Some((pending, pending_start));
+            return Ok(());
+        };
+
+        self.write_patch(pending, start.saturating_sub(pending_start))?;
+        let patch = self.take_patch(width, &raw, rect);
+        self.pending = Some((patch, start));
+
+        Ok(())
+    }
+
+    /// Write the last frame, the PNG trailer and the frame count, nothing without frames.
+    pub fn finish(mut self) -> XCapResult<()> {
+        self.write_trailer()
+    }
+
+    fn write_trailer(&mut self) -> XCapResult<()> {
+        let Some((pending, _)) = self.pending.take() else {
+            return Ok(());
+        };
+
+        let delay = self.options.last_frame_delay.as_millis() as u64;
+        self.write_patch(pending, delay)?;
+        write_chunk(&mut self.writer, b"IEND", &[])?;
+
+        let end = self.writer.stream_position()?;
+        self.writer.seek(SeekFrom::Start(self.actl_position))?;
+        self.write_actl(self.num_frames)?;
+        self.writer.seek(SeekFrom::Start(end))?;
+        self.writer.flush()?;
+
+        Ok(())
+    }
+}
+
+impl_frame_sink!(ApngWriter, Write, Seek);
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::test_support::frame_at;
+
+    #[test]
+    fn test_apng_patches() {
+        let frame = |pixels: &[usize], millis: u64, sequence: u64| {
+            let mut frame = frame_at(4, 2, [0, 0, 255, 255], 1000 + millis).with_sequence(sequence);
+            for pixel in pixels {
+                frame.raw[pixel * 4] = 255;
+            }
+            frame
+        };
+
+        // 序号不连续的帧，脏区域没有覆盖实际变化
+        let after_gap = frame(&[3, 6], 200, 5).with_dirty_rects(vec![DirtyRect {
+            x: 0,
+            y: 0,
+            width: 1,
+            height: 1,
+        }]);
+
+        let mut apng = Cursor::new(Vec::new());
+        let mut writer = ApngWriter::new(&mut apng).unwrap();
+        for frame in [
+            frame(&[], 0, 0),
+            frame(&[], 40, 1),
+            frame(&[6], 100, 2),
+            after_gap,
+        ] {
+            writer.write_frame(&frame).unwrap();
+        }
+        writer.finish().unwrap();
+
+        apng.set_position(0);
+        let mut reader = png::Decoder::new(apng).read_info().unwrap();
+        assert_eq!(reader.info().animation_control.unwrap().num_frames, 3);
+
+        let mut buf = vec![0; reader.output_buffer_size().unwrap()];
+        let mut frames = Vec::new();
+        while let Ok(output) = reader.next_frame(&mut buf) {
+            let fctl = reader.info().frame_control.unwrap();
+            frames.push((
+                fctl.x_offset,
+                fctl.y_offset,
+                fctl.width,
+                fctl.height,
+                fctl.delay_num,
+            ));
+            if frames.len() == 2 {
+                assert_eq!(&buf[..output.buffer_size()], &[255, 0, 255, 255]);
+            }
+        }
+
+        assert_eq!(
+            frames,
+            [(0, 0, 4, 2, 100), (2, 1, 1, 1, 100), (3, 0, 1, 1, 100)]
+        );
+    }
+
+    #[test]
+    fn test_apng_without_frames() {
+        let mut apng = Cursor::new(Vec::new());
+        ApngWriter::new(&mut apng).unwrap().finish().unwrap();
+
+        assert!(apng.into_inner().is_empty());
+    }
+}
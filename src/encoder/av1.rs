@@ -0,0 +1,210 @@
+use std::{collections::HashMap, time::Duration};
+
+use rav1e::prelude::{
+    ChromaSampling, ColorDescription, ColorPrimaries, Config, Context, EncoderConfig,
+    EncoderStatus, FrameType, MatrixCoefficients, PixelRange, Rational, SpeedSettings,
+    TransferCharacteristics,
+};
+
+use crate::{
+    error::{XCapError, XCapResult},
+    video_recorder::Frame,
+};
+
+use super::{
+    FrameNormalizer,
+    yuv::{YuvConverter, YuvMatrix},
+};
+
+/// Options for [`Av1Encoder::with_options`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Av1Options {
+    /// Frame rate assumed by rate control, the frame timestamps are kept as they are.
+    pub fps: u32,
+    /// Target bitrate in kbit/s, `None` to encode at a constant `quantizer`.
+    pub bitrate: Option<u32>,
+    /// Quantizer from 0 (best quality) to 255.
+    pub quantizer: u8,
+    /// Encoder speed from 0 (slowest, smallest file) to 10 (fastest).
+    pub speed: u8,
+    /// Encoder threads, 0 for one per CPU.
+    pub threads: usize,
+    /// Maximum number of frames between key frames.
+    pub keyframe_interval: u64,
+}
+
+impl Default for Av1Options {
+    fn default() -> Self {
+        Av1Options {
+            fps: 30,
+            bitrate: None,
+            quantizer: 100,
+            speed: 10,
+            threads: 0,
+            keyframe_interval: 240,
+        }
+    }
+}
+
+/// An encoded AV1 frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Av1Packet {
+    pub data: Vec<u8>,
+    /// Time since the first frame.
+    pub timestamp: Duration,
+    pub keyframe: bool,
+}
+
+/// Encodes recorder frames to AV1 on the CPU.
+///
+/// Frames are converted to limited range BT.709 I420, the encoder is configured for the size
+/// of the first frame. Packets come out with a delay, call [`Av1Encoder::flush`] for the rest.
+pub struct Av1Encoder {
+    options: Av1Options,
+    converter: YuvConverter,
+    context: Option<Context<u8>>,
+    frames: FrameNormalizer,
+    /// Timestamps of the frames sent to the encoder, by input frame number
+    timestamps: HashMap<u64, Duration>,
+    frame_number: u64,
+}
+
+impl Av1Encoder {
+    pub fn new() -> XCapResult<Self> {
+        Av1Encoder::with_options(&Av1Options::default())
+    }
+
+    pub fn with_options(options: &Av1Options) -> XCapResult<Self> {
+        if options.fps == 0 {
+            return Err(XCapError::new("AV1 fps must be greater than 0"));
+        }
+
+        if options.speed > 10 {
+            return Err(XCapError::new(format!(
+                "AV1 speed {} is not in 0..=10",
+                options.speed
+            )));
+        }
+
+        Ok(Av1Encoder {
+            options: options.clone(),
+            converter: YuvConverter::new(YuvMatrix::Bt709, true),
+            context: None,
+            frames: FrameNormalizer::default(),
+            timestamps: HashMap::new(),
+            frame_number: 0,
+        })
+    }
+
+    /// The size of the encoded frames, known after the first frame
+    pub fn size(&self) -> Option<(u32, u32)> {
+        self.frames.size()
+    }
+
+    /// The AV1 codec configuration record for containers, known after the first frame
+    pub fn codec_private(&self) -> Option<Vec<u8>> {
+        self.context
+            .as_ref()
+            .map(|context| context.container_sequence_header())
+    }
+
+    fn new_context(&self, width: u32, height: u32) -> XCapResult<Context<u8>> {
+        let mut encoder_config = EncoderConfig {
+            width: width as usize,
+            height: height as usize,
+            bit_depth: 8,
+            chroma_sampling: ChromaSampling::Cs420,
+            pixel_range: PixelRange::Limited,
+            color_description: Some(ColorDescription {
+                color_primaries: ColorPrimaries::BT709,
+                transfer_characteristics: TransferCharacteristics::SRGB,
+                matrix_coefficients: MatrixCoefficients::BT709,
+            }),
+            time_base: Rational::new(1, self.options.fps as u64),
+            quantizer: self.options.quantizer as usize,
+            max_key_frame_interval: self.options.keyframe_interval,
+            speed_settings: SpeedSettings::from_preset(self.options.speed),
+            ..Default::default()
+        };
+        if let Some(bitrate) = self.options.bitrate {
+            encoder_config.bitrate = (bitrate as i32).saturating_mul(1000);
+        }
+
+        Config::new()
+            .with_encoder_config(encoder_config)
+            .with_threads(self.options.threads)
+            .new_context()
+            .map_err(XCapError::new)
+    }
+
+    fn receive_packets(&mut self) -> XCapResult<Vec<Av1Packet>> {
+        let Some(context) = self.context.as_mut() else {
+            return Ok(Vec::new());
+        };
+
+        let mut packets = Vec::new();
+        loop {
+            match context.receive_packet() {
+                Ok(packet) => packets.push(Av1Packet {
+                    timestamp: self
+                        .timestamps
+                        .remove(&packet.input_frameno)
+                        .unwrap_or_default(),
+                    keyframe: packet.frame_type == FrameType::KEY,
+                    data: packet.data,
+                }),
+                // 编码了一帧但没有输出，继续取
+                Err(EncoderStatus::Encoded) => continue,
+                Err(EncoderStatus::NeedMoreData | EncoderStatus::LimitReached) => break,
+                Err(err) => return Err(XCapError::new(err)),
+            }
+        }
+
+        Ok(packets)
+    }
+
+    /// Send a frame to the encoder, returns the packets that are ready.
+    pub fn encode(&mut self, frame: &Frame) -> XCapResult<Vec<Av1Packet>> {
+        if self.frames.size().is_none() {
+            if frame.width == 0 || frame.height == 0 {
+                return Err(XCapError::new("AV1 frames must not be empty"));
+            }
+            self.context = Some(self.new_context(frame.width, frame.height)?);
+            self.frames.set_size(frame.width, frame.height);
+        }
+        let (frame, elapsed) = self.frames.normalize(frame)?;
+        let (width, height) = (frame.width, frame.height);
+        let yuv = self
+            .converter
+            .convert(width, height, frame.to_rgba_image()?.as_raw());
+
+        self.timestamps.insert(self.frame_number, elapsed);
+        self.frame_number += 1;
+
+        let (chroma_width, chroma_height) = self.converter.chroma_size(width, height);
+        let (y_plane, chroma_planes) = yuv.split_at(width as usize * height as usize);
+        let (u_plane, v_plane) = chroma_planes.split_at(chroma_width * chroma_height);
+
+        let context = self
+            .context
+            .as_mut()
+            .ok_or_else(|| XCapError::new("AV1 encoder is not initialized"))?;
+        let mut input = context.new_frame();
+        input.planes[0].copy_from_raw_u8(y_plane, width as usize, 1);
+        input.planes[1].copy_from_raw_u8(u_plane, chroma_width, 1);
+        input.planes[2].copy_from_raw_u8(v_plane, chroma_width, 1);
+
+        context.send_frame(input).map_err(XCapError::new)?;
+
+        self.receive_packets()
+    }
+
+    /// Encode the frames still buffered, returns the remaining packets.
+    pub fn flush(&mut self) -> XCapResult<Vec<Av1Packet>> {
+        if let Some(context) = self.context.as_mut() {
+            context.flush();
+        }
+
+        self.receive_packets()
+    }
+}
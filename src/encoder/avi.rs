@@ -0,0 +1,346 @@
+use std::io::{Seek, SeekFrom, Write};
+
+use image::codecs::jpeg::JpegEncoder;
+
+use crate::{
+    error::{XCapError, XCapResult},
+    video_recorder::Frame,
+};
+
+use super::FrameNormalizer;
+
+/// AVIF_HASINDEX
+const AVI_HAS_INDEX: u32 = 0x10;
+/// AVIIF_KEYFRAME
+const AVI_KEYFRAME: u32 = 0x10;
+
+/// Options for [`AviWriter::with_options`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct AviOptions {
+    /// JPEG quality from 1 to 100.
+    pub quality: u8,
+    /// The frame rate of the file, frames are placed on a grid of this rate.
+    pub fps: u32,
+}
+
+impl Default for AviOptions {
+    fn default() -> Self {
+        AviOptions {
+            quality: 80,
+            fps: 30,
+        }
+    }
+}
+
+/// Writes recorder frames as Motion JPEG in an AVI file.
+///
+/// Frames are placed on the fixed [`AviOptions::fps`] grid, a frame held for several periods
+/// is stored once and indexed for each of them. The frame size is set in the headers by the
+/// first frame. [`AviWriter::finish`] appends the `idx1` index and goes back to fill in the
+/// RIFF sizes and frame counts. AVI files without OpenDML extensions are limited to 4 GB.
+pub struct AviWriter<W: Write + Seek> {
+    writer: W,
+    options: AviOptions,
+    frames: FrameNormalizer,
+    /// Where the RIFF header starts
+    header_position: u64,
+    /// Where the `movi` list type starts, index offsets are relative to it
+    movi_position: u64,
+    /// Offsets and sizes of the written frames
+    index: Vec<(u32, u32)>,
+    max_frame_size: u32,
+    /// The latest frame in JPEG, written once the next frame is due
+    pending: Option<Vec<u8>>,
+    /// The index entry of the pending frame once written, repeats point at the same chunk
+    pending_entry: Option<(u32, u32)>,
+}
+
+impl<W: Write + Seek> AviWriter<W> {
+    pub fn new(writer: W) -> XCapResult<Self> {
+        AviWriter::with_options(writer, &AviOptions::default())
+    }
+
+    pub fn with_options(writer: W, options: &AviOptions) -> XCapResult<Self> {
+        if !(1..=100).contains(&options.quality) {
+            return Err(XCapError::new(format!(
+                "JPEG quality {} is not in 1..=100",
+                options.quality
+            )));
+        }
+
+        if options.fps == 0 {
+            return Err(XCapError::new("AVI fps must be greater than 0"));
+        }
+
+        Ok(AviWriter {
+            writer,
+            options: options.clone(),
+            frames: FrameNormalizer::default(),
+            header_position: 0,
+            movi_position: 0,
+            index: Vec::new(),
+            max_frame_size: 0,
+            pending: None,
+            pending_entry: None,
+        })
+    }
+
+    /// Everything before the first frame, the same size whatever the frame count is
+    fn header(&self, riff_size: u32, movi_size: u32) -> Vec<u8> {
+        let (width, height) = self.frames.size().unwrap_or_default();
+        let num_frames = self.index.len() as u32;
+        let fps = self.options.fps;
+
+        let mut avih = Vec::with_capacity(64);
+        avih.extend_from_slice(b"avih");
+        avih.extend_from_slice(&56u32.to_le_bytes());
+        for value in [
+            1_000_000 / fps,
+            self.max_frame_size.saturating_mul(fps),
+            0,
+            AVI_HAS_INDEX,
+            num_frames,
+            0,
+            1,
+            self.max_frame_size,
+            width,
+            height,
+            0,
+            0,
+            0,
+            0,
+        ] {
+            avih.extend_from_slice(&value.to_le_bytes());
+        }
+
+        let mut strh = Vec::with_capacity(64);
+        strh.extend_from_slice(b"strh");
+        strh.extend_from_slice(&56u32.to_le_bytes());
+        strh.extend_from_slice(b"vidsMJPG");
+        for value in [
+            0,
+            0,
+            0,
+            1,
+            fps,
+            0,
+            num_frames,
+            self.max_frame_size,
+            u32::MAX,
+            0,
+        ] {
+            strh.extend_from_slice(&value.to_le_bytes());
+        }
+        for value in [0, 0, width as u16, height as u16] {
+            strh.extend_from_slice(&value.to_le_bytes());
+        }
+
+        let mut strf = Vec::with_capacity(48);
+        strf.extend_from_slice(b"strf");
+        strf.extend_from_slice(&40u32.to_le_bytes());
+        strf.extend_from_slice(&40u32.to_le_bytes());
+        strf.extend_from_slice(&width.to_le_bytes());
+        strf.extend_from_slice(&height.to_le_bytes());
+        strf.extend_from_slice(&1u16.to_le_bytes());
+        strf.extend_from_slice(&24u16.to_le_bytes());
+        strf.extend_from_slice(b"MJPG");
+        for value in [width.saturating_mul(height).saturating_mul(3), 0, 0, 0, 0] {
+            strf.extend_from_slice(&value.to_le_bytes());
+        }
+
+        let strl_size = 4 + strh.len() + strf.len();
+        let hdrl_size = 4 + avih.len() + 8 + strl_size;
+
+        let mut header = Vec::with_capacity(hdrl_size + 32);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&riff_size.to_le_bytes());
+        header.extend_from_slice(b"AVI LIST");
+        header.extend_from_slice(&(hdrl_size as u32).to_le_bytes());
+        header.extend_from_slice(b"hdrl");
+        header.extend_from_slice(&avih);
+        header.extend_from_slice(b"LIST");
+        header.extend_from_slice(&(strl_size as u32).to_le_bytes());
+        header.extend_from_slice(b"strl");
+        header.extend_from_slice(&strh);
+        header.extend_from_slice(&strf);
+        header.extend_from_slice(b"LIST");
+        header.extend_from_slice(&movi_size.to_le_bytes());
+        header.extend_from_slice(b"movi");
+
+        header
+    }
+
+    fn write_header(&mut self, width: u32, height: u32) -> XCapResult<()> {
+        if width == 0 || height == 0 {
+            return Err(XCapError::new("AVI frames must not be empty"));
+        }
+        if width > u16::MAX as u32 || height > u16::MAX as u32 {
+            return Err(XCapError::new(format!(
+                "{width}x{height} is too large for an AVI"
+            )));
+        }
+
+        self.frames.set_size(width, height);
+        self.header_position = self.writer.stream_position()?;
+
+        // 大小在 finish 时回写
+        let header = self.header(0, 0);
+        self.writer.write_all(&header)?;
+        self.movi_position = self.writer.stream_position()? - 4;
+
+        Ok(())
+    }
+
+    fn write_pending(&mut self) -> XCapResult<()> {
+        let Some(pending) = &self.pending else {
+            return Ok(());
+        };
+
+        // 重复的帧只写索引，指向同一个块
+        if let Some(entry) = self.pending_entry {
+            self.index.push(entry);
+            return Ok(());
+        }
+
+        let offset = self.writer.stream_position()? - self.movi_position;
+        let size = pending.len() as u32;
+        if offset + size as u64 > u32::MAX as u64 {
+            return Err(XCapError::new("AVI file is larger than 4 GB"));
+        }
+
+        self.writer.write_all(b"00dc")?;
+        self.writer.write_all(&size.to_le_bytes())?;
+        self.writer.write_all(pending)?;
+        // 块按 2 字节对齐
+        if size % 2 == 1 {
+            self.writer.write_all(&[0])?;
+        }
+
+        self.index.push((offset as u32, size));
+        self.pending_entry = Some((offset as u32, size));
+        self.max_frame_size = self.max_frame_size.max(size);
+
+        Ok(())
+    }
+
+    pub fn write_frame(&mut self, frame: &Frame) -> XCapResult<()> {
+        if self.frames.size().is_none() {
+            self.write_header(frame.width, frame.height)?;
+        }
+        let (frame, elapsed) = self.frames.normalize(frame)?;
+        let image = frame.to_rgb_image()?;
+        let index = (elapsed.as_secs_f64() * self.options.fps as f64).round() as usize;
+
+        // 上一帧重复写入到当前帧的位置
+        while self.pending.is_some() && self.index.len() < index {
+            self.write_pending()?;
+        }
+
+        let mut jpeg = Vec::new();
+        JpegEncoder::new_with_quality(&mut jpeg, self.options.quality).encode_image(&image)?;
+        self.pending = Some(jpeg);
+        self.pending_entry = None;
+
+        Ok(())
+    }
+
+    /// Write the last frame, the index and the frame count.
+    pub fn finish(mut self) -> XCapResult<()> {
+        self.write_trailer()
+    }
+
+    fn write_trailer(&mut self) -> XCapResult<()> {
+        if self.pending.is_none() {
+            return Err(XCapError::new("No frames to write"));
+        }
+
+        self.write_pending()?;
+        self.pending = None;
+        let movi_end = self.writer.stream_position()?;
+
+        let mut idx1 = Vec::with_capacity(self.index.len() * 16 + 8);
+        idx1.extend_from_slice(b"idx1");
+        idx1.extend_from_slice(&(self.index.len() as u32 * 16).to_le_bytes());
+        for &(offset, size) in &self.index {
+            idx1.extend_from_slice(b"00dc");
+            idx1.extend_from_slice(&AVI_KEYFRAME.to_le_bytes());
+            idx1.extend_from_slice(&offset.to_le_bytes());
+            idx1.extend_from_slice(&size.to_le_bytes());
+        }
+        self.writer.write_all(&idx1)?;
+
+        let end = self.writer.stream_position()?;
+        let riff_size = u32::try_from(end - self.header_position - 8)
+            .map_err(|_| XCapError::new("AVI file is larger than 4 GB"))?;
+        let movi_size = (movi_end - self.movi_position) as u32;
+
+        let header = self.header(riff_size, movi_size);
+        self.writer.seek(SeekFrom::Start(self.header_position))?;
+        self.writer.write_all(&header)?;
+        self.writer.seek(SeekFrom::Start(end))?;
+        self.writer.flush()?;
+
+        Ok(())
+    }
+}
+
+impl_frame_sink!(AviWriter, Write, Seek);
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::test_support::frame_at;
+
+    #[test]
+    fn test_avi_index() {
+        let frame = |color: u8, millis: u64| frame_at(5, 3, [color, 0, 0, 255], 200 + millis);
+
+        let mut avi = Cursor::new(Vec::new());
+        let mut writer = AviWriter::with_options(
+            &mut avi,
+            &AviOptions {
+                fps: 10,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        for frame in [frame(0, 0), frame(255, 200), frame(128, 210)] {
+            writer.write_frame(&frame).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let avi = avi.into_inner();
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(avi[offset..offset + 4].try_into().unwrap());
+        let find = |pattern: &[u8]| {
+            avi.windows(pattern.len())
+                .position(|window| window == pattern)
+                .unwrap()
+        };
+
+        assert_eq!(&avi[..4], b"RIFF");
+        assert_eq!(u32_at(4) as usize, avi.len() - 8);
+        // avih 的总帧数
+        assert_eq!(u32_at(find(b"avih") + 24), 3);
+
+        let movi = find(b"movi");
+        let idx1 = find(b"idx1");
+        assert_eq!(u32_at(idx1 + 4), 3 * 16);
+        for entry in 0..3 {
+            let offset = u32_at(idx1 + 8 + entry * 16 + 8) as usize;
+            let size = u32_at(idx1 + 8 + entry * 16 + 12) as usize;
+            assert_eq!(&avi[movi + offset..movi + offset + 4], b"00dc");
+            // JPEG 以 SOI 开头
+            assert_eq!(&avi[movi + offset + 8..movi + offset + 10], &[0xFF, 0xD8]);
+            assert_eq!(u32_at(movi + offset + 4) as usize, size);
+        }
+        // 重复的帧指向同一个块
+        assert_eq!(u32_at(idx1 + 8 + 8), u32_at(idx1 + 8 + 16 + 8));
+        assert_eq!(
+            avi.windows(4).filter(|window| window == b"00dc").count(),
+            2 + 3
+        );
+    }
+}
@@ -0,0 +1,212 @@
+use std::{io::Write, time::Duration};
+
+use image::{
+    Delay, Frame as ImageFrame, RgbaImage,
+    codecs::gif::{GifEncoder, Repeat},
+};
+
+use crate::{
+    error::{XCapError, XCapResult},
+    video_recorder::Frame,
+};
+
+use super::FrameNormalizer;
+
+/// Options for [`GifWriter::with_options`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct GifOptions {
+    /// Palette quantization speed, from 1 (best quality) to 30 (fastest).
+    pub speed: i32,
+    /// Scale frames down to fit in this width and height, keeping the aspect ratio.
+    pub max_size: Option<(u32, u32)>,
+    /// Play the animation in a loop instead of once.
+    pub repeat: bool,
+    /// How long the last frame is shown.
+    pub last_frame_delay: Duration,
+}
+
+impl Default for GifOptions {
+    fn default() -> Self {
+        GifOptions {
+            speed: 10,
+            max_size: None,
+            repeat: true,
+            last_frame_delay: Duration::from_millis(100),
+        }
+    }
+}
+
+/// GIF delays are in hundredths of a second
+fn to_centiseconds(duration: Duration) -> u64 {
+    (duration.as_millis() as u64 + 5) / 10
+}
+
+/// Writes recorder frames as an animated GIF, each frame with its own palette.
+///
+/// Frame delays come from the frame timestamps and identical consecutive frames are merged.
+/// The GIF takes the size of the first frame, shrunk to fit [`GifOptions::max_size`].
+pub struct GifWriter<W: Write> {
+    encoder: GifEncoder<W>,
+    options: GifOptions,
+    frames: FrameNormalizer,
+    /// The frame waiting for the next one to know its delay, and its start in centiseconds
+    pending: Option<(RgbaImage, u64)>,
+}
+
+impl<W: Write> GifWriter<W> {
+    pub fn new(writer: W) -> XCapResult<Self> {
+        GifWriter::with_options(writer, &GifOptions::default())
+    }
+
+    pub fn with_options(writer: W, options: &GifOptions) -> XCapResult<Self> {
+        if !(1..=30).contains(&options.speed) {
+            return Err(XCapError::new(format!(
+                "GIF speed {} is not in 1..=30",
+                options.speed
+            )));
+        }
+
+        if let Some((width, height)) = options.max_size
+            && (width == 0 || height == 0)
+        {
+            return Err(XCapError::new("GIF max size must be greater than 0"));
+        }
+
+        let mut encoder = GifEncoder::new_with_speed(writer, options.speed);
+        encoder.set_repeat(if options.repeat {
+            Repeat::Infinite
+        } else {
+            Repeat::Finite(0)
+        })?;
+
+        Ok(GifWriter {
+            encoder,
+            options: options.clone(),
+            frames: FrameNormalizer::default(),
+            pending: None,
+        })
+    }
+
+    /// Set the GIF size from the first frame and `max_size`
+    fn set_size(&mut self, frame: &Frame) -> XCapResult<()> {
+        let (mut width, mut height) = (frame.width, frame.height);
+        if let Some((max_width, max_height)) = self.options.max_size {
+            let scale = (max_width as f64 / width as f64)
+                .min(max_height as f64 / height as f64)
+                .min(1.0);
+            width = ((width as f64 * scale).round() as u32).max(1);
+            height = ((height as f64 * scale).round() as u32).max(1);
+        }
+
+        if width > u16::MAX as u32 || height > u16::MAX as u32 {
+            return Err(XCapError::new(format!(
+                "{width}x{height} is too large for a GIF"
+            )));
+        }
+
+        self.frames.set_size(width, height);
+
+        Ok(())
+    }
+
+    fn encode(&mut self, image: RgbaImage, delay: u64) -> XCapResult<()> {
+        let delay = Delay::from_numer_denom_ms((delay * 10).min(u16::MAX as u64 * 10) as u32, 1);
+        self.encoder
+            .encode_frame(ImageFrame::from_parts(image, 0, 0, delay))?;
+
+        Ok(())
+    }
+
+    pub fn write_frame(&mut self, frame: &Frame) -> XCapResult<()> {
+        if self.frames.size().is_none() {
+            self.set_size(frame)?;
+        }
+        let (frame, elapsed) = self.frames.normalize(frame)?;
+        let image = frame.to_rgba_image()?;
+        let start = to_centiseconds(elapsed);
+
+        let Some((pending, pending_start)) = self.pending.take() else {
+            self.pending = Some((image, start));
+            return Ok(());
+        };
+
+        // 内容相同的帧合并为一帧，延长显示时间
+        if pending == image {
+            self.pending = Some((pending, pending_start));
+            return Ok(());
+        }
+
+        // 多数浏览器会把小于 2 的延迟当作 10 处理，间隔太短的帧至少显示 2，从下一帧的延迟中扣回
+        let delay = start.saturating_sub(pending_start).max(2);
+        self.encode(pending, delay)?;
+        self.pending = Some((image, pending_start + delay));
+
+        Ok(())
+    }
+
+    /// Write the last frame and the GIF trailer, nothing is written without frames.
+    pub fn finish(mut self) -> XCapResult<()> {
+        self.write_trailer()
+    }
+
+    fn write_trailer(&mut self) -> XCapResult<()> {
+        let Some((pending, _)) = self.pending.take() else {
+            return Ok(());
+        };
+
+        let delay = to_centiseconds(self.options.last_frame_delay).max(2);
+        self.encode(pending, delay)
+    }
+}
+
+impl_frame_sink!(GifWriter, Write);
+
+#[cfg(test)]
+mod tests {
+    use image::{AnimationDecoder, codecs::gif::GifDecoder};
+
+    use super::*;
+    use crate::test_support::frame_at;
+
+    #[test]
+    fn test_gif_delays_and_duplicates() {
+        let frame = |color: u8, millis: u64| frame_at(4, 2, [color, 0, 0, 255], 1000 + millis);
+
+        let mut gif = Vec::new();
+        let mut writer = GifWriter::with_options(
+            &mut gif,
+            &GifOptions {
+                max_size: Some((2, 2)),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        for frame in [frame(0, 0), frame(0, 100), frame(255, 300), frame(0, 310)] {
+            writer.write_frame(&frame).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let frames = GifDecoder::new(std::io::Cursor::new(gif))
+            .unwrap()
+            .into_frames()
+            .collect_frames()
+            .unwrap();
+        let delays: Vec<u32> = frames
+            .iter()
+            .map(|frame| frame.delay().numer_denom_ms().0)
+            .collect();
+
+        assert_eq!(frames[0].buffer().dimensions(), (2, 1));
+        // 间隔太短的红色帧没有被丢弃
+        assert_eq!(frames[1].buffer().get_pixel(0, 0).0, [255, 0, 0, 255]);
+        assert_eq!(delays, [300, 20, 100]);
+    }
+
+    #[test]
+    fn test_gif_without_frames() {
+        let mut gif = Vec::new();
+        GifWriter::new(&mut gif).unwrap().finish().unwrap();
+
+        assert!(gif.is_empty());
+    }
+}
@@ -0,0 +1,121 @@
+use std::io::{Seek, SeekFrom, Write};
+
+use crate::{error::XCapResult, video_recorder::Frame};
+
+use super::av1::{Av1Encoder, Av1Options, Av1Packet};
+
+/// Writes recorder frames as AV1 in an IVF file, playable with ffmpeg, VLC and others.
+///
+/// Timestamps are in milliseconds since the first frame. [`IvfWriter::finish`] goes back to
+/// put the frame count in the IVF header.
+pub struct IvfWriter<W: Write + Seek> {
+    writer: W,
+    encoder: Av1Encoder,
+    /// Where the IVF header starts
+    header_position: Option<u64>,
+    num_frames: u32,
+}
+
+impl<W: Write + Seek> IvfWriter<W> {
+    pub fn new(writer: W) -> XCapResult<Self> {
+        IvfWriter::with_options(writer, &Av1Options::default())
+    }
+
+    pub fn with_options(writer: W, options: &Av1Options) -> XCapResult<Self> {
+        Ok(IvfWriter {
+            writer,
+            encoder: Av1Encoder::with_options(options)?,
+            header_position: None,
+            num_frames: 0,
+        })
+    }
+
+    fn write_header(&mut self) -> XCapResult<()> {
+        let (width, height) = self.encoder.size().unwrap_or_default();
+
+        let mut header = [0; 32];
+        header[..4].copy_from_slice(b"DKIF");
+        header[6..8].copy_from_slice(&32u16.to_le_bytes());
+        header[8..12].copy_from_slice(b"AV01");
+        header[12..14].copy_from_slice(&(width as u16).to_le_bytes());
+        header[14..16].copy_from_slice(&(height as u16).to_le_bytes());
+        // 时间基为 1/1000 秒
+        header[16..20].copy_from_slice(&1000u32.to_le_bytes());
+        header[20..24].copy_from_slice(&1u32.to_le_bytes());
+        header[24..28].copy_from_slice(&self.num_frames.to_le_bytes());
+
+        self.writer.write_all(&header)?;
+
+        Ok(())
+    }
+
+    fn write_packets(&mut self, packets: Vec<Av1Packet>) -> XCapResult<()> {
+        if self.header_position.is_none() && !packets.is_empty() {
+            self.header_position = Some(self.writer.stream_position()?);
+            self.write_header()?;
+        }
+
+        for packet in packets {
+            self.writer
+                .write_all(&(packet.data.len() as u32).to_le_bytes())?;
+            self.writer
+                .write_all(&(packet.timestamp.as_millis() as u64).to_le_bytes())?;
+            self.writer.write_all(&packet.data)?;
+            self.num_frames += 1;
+        }
+
+        Ok(())
+    }
+
+    pub fn write_frame(&mut self, frame: &Frame) -> XCapResult<()> {
+        let packets = self.encoder.encode(frame)?;
+        self.write_packets(packets)
+    }
+
+    /// Encode the buffered frames, then write the frame count.
+    pub fn finish(mut self) -> XCapResult<()> {
+        self.write_trailer()
+    }
+
+    fn write_trailer(&mut self) -> XCapResult<()> {
+        let packets = self.encoder.flush()?;
+        self.write_packets(packets)?;
+
+        if let Some(header_position) = self.header_position {
+            let end = self.writer.stream_position()?;
+            self.writer.seek(SeekFrom::Start(header_position))?;
+            self.write_header()?;
+            self.writer.seek(SeekFrom::Start(end))?;
+        }
+        self.writer.flush()?;
+
+        Ok(())
+    }
+}
+
+impl_frame_sink!(IvfWriter, Write, Seek);
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::test_support::frame_at;
+
+    #[test]
+    fn test_ivf_frames() {
+        let mut ivf = Cursor::new(Vec::new());
+        let mut writer = IvfWriter::new(&mut ivf).unwrap();
+        for (index, color) in [0, 128, 255].into_iter().enumerate() {
+            let frame = frame_at(64, 48, [color, 0, 0, 255], 40 * index as u64);
+            writer.write_frame(&frame).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let ivf = ivf.into_inner();
+        assert_eq!(&ivf[..4], b"DKIF");
+        assert_eq!(&ivf[8..12], b"AV01");
+        assert_eq!(u16::from_le_bytes([ivf[12], ivf[13]]), 64);
+        assert_eq!(u32::from_le_bytes([ivf[24], ivf[25], ivf[26], ivf[27]]), 3);
+    }
+}
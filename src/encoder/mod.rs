@@ -0,0 +1,72 @@
+use std::{borrow::Cow, time::Duration};
+
+use crate::{error::XCapResult, video_recorder::Frame};
+
+/// Implement [`crate::FrameSink`] for a writer with `write_frame` and `write_trailer`
+macro_rules! impl_frame_sink {
+    ($writer:ident, $($bound:ident),+) => {
+        impl<W: $($bound +)+ Send> crate::pipeline::FrameSink for $writer<W> {
+            fn on_frame(&mut self, frame: &Frame) -> XCapResult<()> {
+                self.write_frame(frame)
+            }
+
+            fn finish(&mut self) -> XCapResult<()> {
+                self.write_trailer()
+            }
+        }
+    };
+}
+
+mod apng;
+#[cfg(feature = "encode")]
+mod av1;
+mod avi;
+mod gif;
+#[cfg(feature = "encode")]
+mod ivf;
+mod webm;
+mod y4m;
+pub(crate) mod yuv;
+
+pub use apng::{ApngOptions, ApngWriter};
+#[cfg(feature = "encode")]
+pub use av1::{Av1Encoder, Av1Options, Av1Packet};
+pub use avi::{AviOptions, AviWriter};
+pub use gif::{GifOptions, GifWriter};
+#[cfg(feature = "encode")]
+pub use ivf::IvfWriter;
+pub use webm::{WebmMuxer, WebmTrack};
+pub use y4m::{Y4mColorspace, Y4mOptions, Y4mWriter};
+pub use yuv::YuvMatrix;
+
+/// Brings the frames of a stream to one size and times them from the first frame
+#[derive(Debug, Default)]
+pub(crate) struct FrameNormalizer {
+    size: Option<(u32, u32)>,
+    first_timestamp: Option<Duration>,
+}
+
+impl FrameNormalizer {
+    /// The stream size, `None` before the first frame
+    pub fn size(&self) -> Option<(u32, u32)> {
+        self.size
+    }
+
+    /// Set the stream size before the first frame, which is used otherwise
+    pub fn set_size(&mut self, width: u32, height: u32) {
+        self.size = Some((width, height));
+    }
+
+    /// The frame resized to the stream size, and its time since the first frame
+    pub fn normalize<'a>(&mut self, frame: &'a Frame) -> XCapResult<(Cow<'a, Frame>, Duration)> {
+        let (width, height) = *self.size.get_or_insert((frame.width, frame.height));
+        let first_timestamp = *self.first_timestamp.get_or_insert(frame.timestamp);
+        let elapsed = frame.timestamp.saturating_sub(first_timestamp);
+
+        if (frame.width, frame.height) == (width, height) {
+            Ok((Cow::Borrowed(frame), elapsed))
+        } else {
+            Ok((Cow::Owned(frame.resize(width, height)?), elapsed))
+        }
+    }
+}
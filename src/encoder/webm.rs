@@ -0,0 +1,443 @@
+use std::{
+    io::{Seek, SeekFrom, Write},
+    time::Duration,
+};
+
+use crate::{
+    error::{XCapError, XCapResult},
+    video_recorder::Frame,
+};
+
+// EBML 和 Matroska 元素 ID
+const EBML: u32 = 0x1A45DFA3;
+const EBML_VERSION: u32 = 0x4286;
+const EBML_READ_VERSION: u32 = 0x42F7;
+const EBML_MAX_ID_LENGTH: u32 = 0x42F2;
+const EBML_MAX_SIZE_LENGTH: u32 = 0x42F3;
+const DOC_TYPE: u32 = 0x4282;
+const DOC_TYPE_VERSION: u32 = 0x4287;
+const DOC_TYPE_READ_VERSION: u32 = 0x4285;
+const SEGMENT: u32 = 0x18538067;
+const SEEK_HEAD: u32 = 0x114D9B74;
+const SEEK: u32 = 0x4DBB;
+const SEEK_ID: u32 = 0x53AB;
+const SEEK_POSITION: u32 = 0x53AC;
+const INFO: u32 = 0x1549A966;
+const TIMESTAMP_SCALE: u32 = 0x2AD7B1;
+const MUXING_APP: u32 = 0x4D80;
+const WRITING_APP: u32 = 0x5741;
+const DURATION: u32 = 0x4489;
+const TRACKS: u32 = 0x1654AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const TRACK_UID: u32 = 0x73C5;
+const TRACK_TYPE: u32 = 0x83;
+const FLAG_LACING: u32 = 0x9C;
+const CODEC_ID: u32 = 0x86;
+const CODEC_PRIVATE: u32 = 0x63A2;
+const VIDEO: u32 = 0xE0;
+const PIXEL_WIDTH: u32 = 0xB0;
+const PIXEL_HEIGHT: u32 = 0xBA;
+const CLUSTER: u32 = 0x1F43B675;
+const TIMESTAMP: u32 = 0xE7;
+const SIMPLE_BLOCK: u32 = 0xA3;
+const CUES: u32 = 0x1C53BB6B;
+const CUE_POINT: u32 = 0xBB;
+const CUE_TIME: u32 = 0xB3;
+const CUE_TRACK_POSITIONS: u32 = 0xB7;
+const CUE_TRACK: u32 = 0xF7;
+const CUE_CLUSTER_POSITION: u32 = 0xF1;
+
+/// Codecs allowed in WebM, others are written with the Matroska doc type
+const WEBM_CODECS: [&str; 3] = ["V_AV1", "V_VP8", "V_VP9"];
+
+/// A new cluster starts at least this often, in milliseconds
+const CLUSTER_DURATION: i64 = 5000;
+
+fn write_id(buf: &mut Vec<u8>, id: u32) {
+    let bytes = id.to_be_bytes();
+    let skip = bytes.iter().take_while(|&&byte| byte == 0).count().min(3);
+    buf.extend_from_slice(&bytes[skip..]);
+}
+
+/// Variable length size, in as few bytes as possible
+fn write_size(buf: &mut Vec<u8>, size: u64) {
+    // 全 1 的值保留为未知大小
+    let length = (1..8)
+        .find(|length| size < (1 << (7 * length)) - 1)
+        .unwrap_or(8);
+    let bytes = (size | 1 << (7 * length)).to_be_bytes();
+    buf.extend_from_slice(&bytes[8 - length..]);
+}
+
+fn element(buf: &mut Vec<u8>, id: u32, data: &[u8]) {
+    write_id(buf, id);
+    write_size(buf, data.len() as u64);
+    buf.extend_from_slice(data);
+}
+
+fn uint_element(buf: &mut Vec<u8>, id: u32, value: u64) {
+    let bytes = value.to_be_bytes();
+    let skip = bytes.iter().take_while(|&&byte| byte == 0).count().min(7);
+    element(buf, id, &bytes[skip..]);
+}
+
+/// An unsigned integer that always takes 8 bytes, so it can be rewritten in place
+fn fixed_uint_element(buf: &mut Vec<u8>, id: u32, value: u64) {
+    element(buf, id, &value.to_be_bytes());
+}
+
+/// The video track of a [`WebmMuxer`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebmTrack {
+    /// Matroska codec ID, e.g. `V_AV1`, `V_VP9` or `V_MPEG4/ISO/AVC`.
+    pub codec_id: String,
+    /// Codec setup data, e.g. the AV1 codec configuration record.
+    pub codec_private: Option<Vec<u8>>,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Writes encoded video packets into a WebM file, or Matroska for codecs WebM doesn't allow.
+///
+/// Packets are stored as SimpleBlocks in clusters starting at key frames, with a cue per
+/// cluster for seeking. Timestamps are in milliseconds. [`WebmMuxer::finish`] appends the
+/// cues and goes back to fill in the segment size and duration.
+pub struct WebmMuxer<W: Write + Seek> {
+    writer: W,
+    /// Where the segment data starts, positions inside the segment are relative to it
+    segment_position: u64,
+    /// Where the duration value of the info starts
+    duration_position: u64,
+    info_position: u64,
+    tracks_position: u64,
+    /// The blocks of the current cluster and its timestamp
+    cluster: Option<(Vec<u8>, i64)>,
+    /// Cluster timestamps and positions
+    cues: Vec<(i64, u64)>,
+    first_timestamp: Option<Duration>,
+    /// The timestamp of the last packet and how long it lasts
+    last_timestamp: Option<(i64, i64)>,
+}
+
+impl<W: Write + Seek> WebmMuxer<W> {
+    /// Write the file header for `track`.
+    pub fn new(mut writer: W, track: &WebmTrack) -> XCapResult<Self> {
+        if track.width == 0 || track.height == 0 {
+            return Err(XCapError::new("WebM track size must be greater than 0"));
+        }
+
+        let doc_type = if WEBM_CODECS.contains(&track.codec_id.as_str()) {
+            "webm"
+        } else {
+            "matroska"
+        };
+
+        let mut header = Vec::new();
+        uint_element(&mut header, EBML_VERSION, 1);
+        uint_element(&mut header, EBML_READ_VERSION, 1);
+        uint_element(&mut header, EBML_MAX_ID_LENGTH, 4);
+        uint_element(&mut header, EBML_MAX_SIZE_LENGTH, 8);
+        element(&mut header, DOC_TYPE, doc_type.as_bytes());
+        uint_element(&mut header, DOC_TYPE_VERSION, 4);
+        uint_element(&mut header, DOC_TYPE_READ_VERSION, 2);
+
+        let mut buf = Vec::new();
+        element(&mut buf, EBML, &header);
+        // 段大小在 finish 时回写，先写未知大小
+        write_id(&mut buf, SEGMENT);
+        buf.extend_from_slice(&[0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+        writer.write_all(&buf)?;
+
+        let mut muxer = WebmMuxer {
+            segment_position: writer.stream_position()?,
+            writer,
+            duration_position: 0,
+            info_position: 0,
+            tracks_position: 0,
+            cluster: None,
+            cues: Vec::new(),
+            first_timestamp: None,
+            last_timestamp: None,
+        };
+
+        let mut info = Vec::new();
+        uint_element(&mut info, TIMESTAMP_SCALE, 1_000_000);
+        element(&mut info, MUXING_APP, b"xcap");
+        element(&mut info, WRITING_APP, b"xcap");
+        element(&mut info, DURATION, &0f64.to_be_bytes());
+
+        let mut video = Vec::new();
+        uint_element(&mut video, PIXEL_WIDTH, track.width as u64);
+        uint_element(&mut video, PIXEL_HEIGHT, track.height as u64);
+
+        let mut track_entry = Vec::new();
+        uint_element(&mut track_entry, TRACK_NUMBER, 1);
+        uint_element(&mut track_entry, TRACK_UID, 1);
+        uint_element(&mut track_entry, TRACK_TYPE, 1);
+        uint_element(&mut track_entry, FLAG_LACING, 0);
+        element(&mut track_entry, CODEC_ID, track.codec_id.as_bytes());
+        if let Some(codec_private) = &track.codec_private {
+            element(&mut track_entry, CODEC_PRIVATE, codec_private);
+        }
+        element(&mut track_entry, VIDEO, &video);
+
+        let mut tracks = Vec::new();
+        element(&mut tracks, TRACK_ENTRY, &track_entry);
+
+        let seek_head_size = muxer.seek_head(0).len() as u64;
+        let mut buf = Vec::new();
+        element(&mut buf, INFO, &info);
+        muxer.info_position = seek_head_size;
+        // Duration 是 Info 的最后一个元素，值占最后 8 字节
+        muxer.duration_position = muxer.segment_position + seek_head_size + buf.len() as u64 - 8;
+        muxer.tracks_position = seek_head_size + buf.len() as u64;
+        element(&mut buf, TRACKS, &tracks);
+
+        let seek_head = muxer.seek_head(0);
+        muxer.writer.write_all(&seek_head)?;
+        muxer.writer.write_all(&buf)?;
+
+        Ok(muxer)
+    }
+
+    /// The seek head, the same size whatever `cues_position` is
+    fn seek_head(&self, cues_position: u64) -> Vec<u8> {
+        let mut seek_head = Vec::new();
+        for (id, position) in [
+            (INFO, self.info_position),
+            (TRACKS, self.tracks_position),
+            (CUES, cues_position),
+        ] {
+            let mut seek = Vec::new();
+            element(&mut seek, SEEK_ID, &id.to_be_bytes());
+            fixed_uint_element(&mut seek, SEEK_POSITION, position);
+            element(&mut seek_head, SEEK, &seek);
+        }
+
+        let mut buf = Vec::new();
+        element(&mut buf, SEEK_HEAD, &seek_head);
+
+        buf
+    }
+
+    fn write_cluster(&mut self) -> XCapResult<()> {
+        let Some((blocks, timestamp)) = self.cluster.take() else {
+            return Ok(());
+        };
+
+        let mut cluster = Vec::with_capacity(blocks.len() + 16);
+        uint_element(&mut cluster, TIMESTAMP, timestamp as u64);
+        cluster.extend_from_slice(&blocks);
+
+        let mut buf = Vec::with_capacity(cluster.len() + 12);
+        element(&mut buf, CLUSTER, &cluster);
+        self.writer.write_all(&buf)?;
+
+        Ok(())
+    }
+
+    /// Add a packet `timestamp` after the start of the recording.
+    pub fn write_packet(
+        &mut self,
+        data: &[u8],
+        timestamp: Duration,
+        keyframe: bool,
+    ) -> XCapResult<()> {
+        let timestamp = timestamp.as_millis() as i64;
+
+        if self.last_timestamp.is_none() && !keyframe {
+            return Err(XCapError::new("The first packet must be a key frame"));
+        }
+
+        // 关键帧开始新的簇，块的相对时间戳只有 16 位
+        let new_cluster = match &self.cluster {
+            Some((blocks, cluster_timestamp)) => {
+                let relative = timestamp - cluster_timestamp;
+                (keyframe && !blocks.is_empty())
+                    || relative >= CLUSTER_DURATION
+                    || relative < i16::MIN as i64
+            }
+            None => true,
+        };
+        if new_cluster {
+            self.write_cluster()?;
+
+            let position = self.writer.stream_position()? - self.segment_position;
+            if keyframe {
+                self.cues.push((timestamp, position));
+            }
+            self.cluster = Some((Vec::new(), timestamp));
+        }
+
+        let Some((blocks, cluster_timestamp)) = self.cluster.as_mut() else {
+            return Err(XCapError::new("No cluster to write to"));
+        };
+
+        let mut block = Vec::with_capacity(data.len() + 4);
+        // 轨道号 1，相对时间戳，关键帧标记
+        block.push(0x81);
+        block.extend_from_slice(&((timestamp - *cluster_timestamp) as i16).to_be_bytes());
+        block.push(if keyframe { 0x80 } else { 0 });
+        block.extend_from_slice(data);
+        element(blocks, SIMPLE_BLOCK, &block);
+
+        self.last_timestamp = Some(match self.last_timestamp {
+            Some((last, _)) if timestamp > last => (timestamp, timestamp - last),
+            Some(last) => last,
+            None => (timestamp, 0),
+        });
+
+        Ok(())
+    }
+
+    /// Add the packet of an encoded `frame`, timed from the first frame passed here.
+    pub fn write_frame_packet(
+        &mut self,
+        data: &[u8],
+        frame: &Frame,
+        keyframe: bool,
+    ) -> XCapResult<()> {
+        let first_timestamp = *self.first_timestamp.get_or_insert(frame.timestamp);
+        self.write_packet(
+            data,
+            frame.timestamp.saturating_sub(first_timestamp),
+            keyframe,
+        )
+    }
+
+    /// Write the last cluster, the cues, the duration and the segment size.
+    pub fn finish(mut self) -> XCapResult<()> {
+        let (last_timestamp, last_duration) = self
+            .last_timestamp
+            .ok_or_else(|| XCapError::new("No packets to write"))?;
+
+        self.write_cluster()?;
+
+        let cues_position = self.writer.stream_position()? - self.segment_position;
+        let mut cues = Vec::new();
+        for &(timestamp, position) in &self.cues {
+            let mut cue_track_positions = Vec::new();
+            uint_element(&mut cue_track_positions, CUE_TRACK, 1);
+            uint_element(&mut cue_track_positions, CUE_CLUSTER_POSITION, position);
+
+            let mut cue_point = Vec::new();
+            uint_element(&mut cue_point, CUE_TIME, timestamp as u64);
+            element(&mut cue_point, CUE_TRACK_POSITIONS, &cue_track_positions);
+
+            element(&mut cues, CUE_POINT, &cue_point);
+        }
+        let mut buf = Vec::new();
+        element(&mut buf, CUES, &cues);
+        self.writer.write_all(&buf)?;
+
+        let end = self.writer.stream_position()?;
+
+        let seek_head = self.seek_head(cues_position);
+        self.writer.seek(SeekFrom::Start(self.segment_position))?;
+        self.writer.write_all(&seek_head)?;
+
+        // 最后一帧按与前一帧的间隔计算时长
+        let duration = (last_timestamp + last_duration) as f64;
+        self.writer.seek(SeekFrom::Start(self.duration_position))?;
+        self.writer.write_all(&duration.to_be_bytes())?;
+
+        let mut segment_size = (end - self.segment_position).to_be_bytes();
+        segment_size[0] = 0x01;
+        self.writer
+            .seek(SeekFrom::Start(self.segment_position - 8))?;
+        self.writer.write_all(&segment_size)?;
+
+        self.writer.seek(SeekFrom::Start(end))?;
+        self.writer.flush()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn read_vint(buf: &[u8], keep_marker: bool) -> (u64, usize) {
+        let length = buf[0].leading_zeros() as usize + 1;
+        let value = buf[..length]
+            .iter()
+            .fold(0, |value, &byte| value << 8 | byte as u64);
+
+        if keep_marker {
+            (value, length)
+        } else {
+            (value & ((1 << (7 * length)) - 1), length)
+        }
+    }
+
+    fn children(mut buf: &[u8]) -> Vec<(u32, &[u8])> {
+        let mut children = Vec::new();
+        while !buf.is_empty() {
+            let (id, id_length) = read_vint(buf, true);
+            let (size, size_length) = read_vint(&buf[id_length..], false);
+            let start = id_length + size_length;
+            children.push((id as u32, &buf[start..start + size as usize]));
+            buf = &buf[start + size as usize..];
+        }
+
+        children
+    }
+
+    fn child(buf: &[u8], id: u32) -> &[u8] {
+        children(buf)
+            .into_iter()
+            .find(|child| child.0 == id)
+            .unwrap()
+            .1
+    }
+
+    #[test]
+    fn test_webm_layout() {
+        let mut webm = Cursor::new(Vec::new());
+        let mut muxer = WebmMuxer::new(
+            &mut webm,
+            &WebmTrack {
+                codec_id: "V_AV1".to_string(),
+                codec_private: None,
+                width: 64,
+                height: 48,
+            },
+        )
+        .unwrap();
+        for (millis, keyframe) in [(0, true), (40, false), (6000, false), (6040, true)] {
+            muxer
+                .write_packet(&[1, 2, 3], Duration::from_millis(millis), keyframe)
+                .unwrap();
+        }
+        muxer.finish().unwrap();
+
+        let webm = webm.into_inner();
+        assert_eq!(child(child(&webm, EBML), DOC_TYPE), b"webm");
+
+        // 段大小回写后，段内元素正好解析到文件末尾
+        let segment = child(&webm, SEGMENT);
+        let ids: Vec<u32> = children(segment).iter().map(|child| child.0).collect();
+        assert_eq!(
+            ids,
+            [SEEK_HEAD, INFO, TRACKS, CLUSTER, CLUSTER, CLUSTER, CUES]
+        );
+
+        let duration = child(child(segment, INFO), DURATION);
+        assert_eq!(f64::from_be_bytes(duration.try_into().unwrap()), 6080.0);
+
+        // 超过 5 秒和遇到关键帧时各开始一个新簇，只有关键帧开头的簇有 cue
+        let cue_points = children(child(segment, CUES));
+        assert_eq!(cue_points.len(), 2);
+        for (_, cue_point) in cue_points {
+            let position = child(child(cue_point, CUE_TRACK_POSITIONS), CUE_CLUSTER_POSITION);
+            let position = position
+                .iter()
+                .fold(0, |value, &byte| value << 8 | byte as usize);
+            assert_eq!(read_vint(&segment[position..], true).0, CLUSTER as u64);
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use std::io::Write;
+
+use crate::{
+    error::{XCapError, XCapResult},
+    video_recorder::Frame,
+};
+
+use super::{
+    FrameNormalizer,
+    yuv::{YuvConverter, YuvMatrix},
+};
+
+/// The chroma layout of a YUV4MPEG2 stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Y4mColorspace {
+    /// Chroma subsampled 2x2, sited in the center (`C420jpeg`).
+    #[default]
+    I420,
+    /// Full resolution chroma (`C444`).
+    I444,
+}
+
+/// Options for [`Y4mWriter::with_options`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Y4mOptions {
+    /// The frame rate declared in the stream header.
+    pub fps: u32,
+    pub colorspace: Y4mColorspace,
+    pub matrix: YuvMatrix,
+}
+
+impl Default for Y4mOptions {
+    fn default() -> Self {
+        Y4mOptions {
+            fps: 30,
+            colorspace: Y4mColorspace::default(),
+            matrix: YuvMatrix::default(),
+        }
+    }
+}
+
+/// Writes recorder frames as a YUV4MPEG2 stream, e.g. for `ffmpeg -f yuv4mpegpipe -i -`.
+///
+/// Every frame period gets the latest frame by then, so ffmpeg sees a constant frame rate.
+/// The stream is sized by the header written for the first frame.
+pub struct Y4mWriter<W: Write> {
+    writer: W,
+    options: Y4mOptions,
+    converter: YuvConverter,
+    frames: FrameNormalizer,
+    /// Frames written so far
+    written: u64,
+    /// The latest frame in YUV, written once the next frame is due
+    pending: Option<Vec<u8>>,
+}
+
+impl<W: Write> Y4mWriter<W> {
+    pub fn new(writer: W) -> XCapResult<Self> {
+        Y4mWriter::with_options(writer, &Y4mOptions::default())
+    }
+
+    pub fn with_options(writer: W, options: &Y4mOptions) -> XCapResult<Self> {
+        if options.fps == 0 {
+            return Err(XCapError::new("Y4M fps must be greater than 0"));
+        }
+
+        Ok(Y4mWriter {
+            writer,
+            options: options.clone(),
+            converter: YuvConverter::new(options.matrix, options.colorspace == Y4mColorspace::I420),
+            frames: FrameNormalizer::default(),
+            written: 0,
+            pending: None,
+        })
+    }
+
+    fn write_header(&mut self, width: u32, height: u32) -> XCapResult<()> {
+        if width == 0 || height == 0 {
+            return Err(XCapError::new("Y4M frames must not be empty"));
+        }
+
+        let colorspace = match self.options.colorspace {
+            Y4mColorspace::I420 => "420jpeg",
+            Y4mColorspace::I444 => "444",
+        };
+        writeln!(
+            self.writer,
+            "YUV4MPEG2 W{width} H{height} F{}:1 Ip A1:1 C{colorspace} XCOLORRANGE=LIMITED",
+            self.options.fps
+        )?;
+
+        self.frames.set_size(width, height);
+
+        Ok(())
+    }
+
+    fn write_pending(&mut self) -> XCapResult<()> {
+        if let Some(pending) = &self.pending {
+            self.writer.write_all(b"FRAME\n")?;
+            self.writer.write_all(pending)?;
+            self.written += 1;
+        }
+
+        Ok(())
+    }
+
+    pub fn write_frame(&mut self, frame: &Frame) -> XCapResult<()> {
+        if self.frames.size().is_none() {
+            self.write_header(frame.width, frame.height)?;
+        }
+        let (frame, elapsed) = self.frames.normalize(frame)?;
+        let (width, height) = (frame.width, frame.height);
+        let image = frame.to_rgba_image()?;
+        let index = (elapsed.as_secs_f64() * self.options.fps as f64).round() as u64;
+
+        // 上一帧重复写入到当前帧的位置
+        while self.pending.is_some() && self.written < index {
+            self.write_pending()?;
+        }
+
+        self.pending = Some(self.converter.convert(width, height, image.as_raw()));
+
+        Ok(())
+    }
+
+    /// Write the last frame and flush the writer, nothing is written without frames.
+    pub fn finish(mut self) -> XCapResult<()> {
+        self.write_trailer()
+    }
+
+    fn write_trailer(&mut self) -> XCapResult<()> {
+        self.write_pending()?;
+        self.pending = None;
+        self.writer.flush()?;
+
+        Ok(())
+    }
+}
+
+impl_frame_sink!(Y4mWriter, Write);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::frame_at;
+
+    #[test]
+    fn test_y4m_odd_size_and_fixed_rate() {
+        let frame = |[r, g, b]: [u8; 3], millis: u64| frame_at(3, 3, [r, g, b, 255], 500 + millis);
+
+        let mut y4m = Vec::new();
+        let mut writer = Y4mWriter::with_options(
+            &mut y4m,
+            &Y4mOptions {
+                fps: 10,
+                matrix: YuvMatrix::Bt601,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        for frame in [
+            frame([255, 255, 255], 0),
+            frame([0, 0, 0], 200),
+            frame([255, 0, 0], 210),
+        ] {
+            writer.write_frame(&frame).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let header = b"YUV4MPEG2 W3 H3 F10:1 Ip A1:1 C420jpeg XCOLORRANGE=LIMITED\n";
+        assert_eq!(&y4m[..header.len()], header);
+
+        // 3x3 的 Y 平面和 2x2 的 U、V 平面
+        let frames: Vec<&[u8]> = y4m[header.len()..].chunks(6 + 9 + 4 * 2).collect();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0], frames[1]);
+        assert_eq!(&frames[0][..6], b"FRAME\n");
+        assert_eq!(
+            (frames[0][6], frames[0][15], frames[0][19]),
+            (235, 128, 128)
+        );
+        assert_eq!((frames[2][6], frames[2][15], frames[2][19]), (81, 90, 240));
+    }
+
+    #[test]
+    fn test_y4m_without_frames() {
+        let mut y4m = Vec::new();
+        Y4mWriter::new(&mut y4m).unwrap().finish().unwrap();
+
+        assert!(y4m.is_empty());
+    }
+}
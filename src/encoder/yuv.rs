@@ -0,0 +1,123 @@
+/// The matrix used to convert RGB to limited range YUV.
+///
+/// YUV4MPEG2 has no field for it, tell ffmpeg with `-colorspace bt470bg` or `-colorspace bt709`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum YuvMatrix {
+    Bt601,
+    #[default]
+    Bt709,
+}
+
+impl YuvMatrix {
+    /// Y, U and V rows of RGB coefficients, in 16.16 fixed point
+    fn coefficients(&self) -> [[i32; 3]; 3] {
+        let (kr, kb) = match self {
+            YuvMatrix::Bt601 => (0.299, 0.114),
+            YuvMatrix::Bt709 => (0.2126, 0.0722),
+        };
+        let kg = 1.0 - kr - kb;
+
+        // 亮度范围 16..=235，色度范围 16..=240
+        let (y_scale, uv_scale) = (219.0 / 255.0, 112.0 / 255.0);
+        let rows: [[f64; 3]; 3] = [
+            [kr * y_scale, kg * y_scale, kb * y_scale],
+            [
+                -kr / (1.0 - kb) * uv_scale,
+                -kg / (1.0 - kb) * uv_scale,
+                uv_scale,
+            ],
+            [
+                uv_scale,
+                -kg / (1.0 - kr) * uv_scale,
+                -kb / (1.0 - kr) * uv_scale,
+            ],
+        ];
+
+        rows.map(|row| row.map(|value| (value * 65536.0).round() as i32))
+    }
+}
+
+/// Converts RGBA to planar YUV, I420 or I444
+#[derive(Debug, Clone)]
+pub(crate) struct YuvConverter {
+    coefficients: [[i32; 3]; 3],
+    /// Pixels per chroma sample in each direction
+    step: usize,
+}
+
+impl YuvConverter {
+    pub fn new(matrix: YuvMatrix, subsampled: bool) -> YuvConverter {
+        YuvConverter {
+            coefficients: matrix.coefficients(),
+            step: if subsampled { 2 } else { 1 },
+        }
+    }
+
+    /// Width and height of the U and V planes
+    pub fn chroma_size(&self, width: u32, height: u32) -> (usize, usize) {
+        (
+            (width as usize).div_ceil(self.step),
+            (height as usize).div_ceil(self.step),
+        )
+    }
+
+    /// The average of `count` RGB pixels whose channels add up to `rgb`, in YUV
+    fn to_yuv(&self, rgb: [i32; 3], count: i32) -> [u8; 3] {
+        let offsets = [16, 128, 128];
+        let mut yuv = [0; 3];
+        for ((value, row), offset) in yuv.iter_mut().zip(&self.coefficients).zip(offsets) {
+            let sum = row[0] * rgb[0] + row[1] * rgb[1] + row[2] * rgb[2];
+            *value = ((sum + (count << 15)).div_euclid(count << 16) + offset).clamp(0, 255) as u8;
+        }
+
+        yuv
+    }
+
+    /// Tightly packed Y, U and V planes of a tightly packed RGBA image
+    pub fn convert(&self, width: u32, height: u32, rgba: &[u8]) -> Vec<u8> {
+        let (chroma_width, chroma_height) = self.chroma_size(width, height);
+        let (width, height) = (width as usize, height as usize);
+        let pixel = |x: usize, y: usize| {
+            let index = (y * width + x) * 4;
+            [
+                rgba[index] as i32,
+                rgba[index + 1] as i32,
+                rgba[index + 2] as i32,
+            ]
+        };
+
+        let chroma_size = chroma_width * chroma_height;
+        let mut yuv = vec![0; width * height + chroma_size * 2];
+        let (y_plane, chroma_planes) = yuv.split_at_mut(width * height);
+        let (u_plane, v_plane) = chroma_planes.split_at_mut(chroma_size);
+
+        for y in 0..height {
+            for x in 0..width {
+                y_plane[y * width + x] = self.to_yuv(pixel(x, y), 1)[0];
+            }
+        }
+
+        // 色度取对应像素的平均值，奇数宽高时边缘只有 1 或 2 个像素
+        let step = self.step;
+        for chroma_y in 0..chroma_height {
+            for chroma_x in 0..chroma_width {
+                let (mut rgb, mut count) = ([0; 3], 0);
+                for y in chroma_y * step..(chroma_y * step + step).min(height) {
+                    for x in chroma_x * step..(chroma_x * step + step).min(width) {
+                        let value = pixel(x, y);
+                        for channel in 0..3 {
+                            rgb[channel] += value[channel];
+                        }
+                        count += 1;
+                    }
+                }
+
+                let [_, u, v] = self.to_yuv(rgb, count);
+                u_plane[chroma_y * chroma_width + chroma_x] = u;
+                v_plane[chroma_y * chroma_width + chroma_x] = v;
+            }
+        }
+
+        yuv
+    }
+}
@@ -10,6 +10,12 @@ pub enum XCapError {
     StdSyncPoisonError(String),
     #[error("Invalid capture region: {0}")]
     InvalidCaptureRegion(String),
+    #[error("Timed out: {0}")]
+    Timeout(String),
+    #[error(transparent)]
+    ImageImageError(#[from] image::ImageError),
+    #[error(transparent)]
+    StdIOError(#[from] std::io::Error),
 
     #[cfg(target_os = "linux")]
     #[error(transparent)]
@@ -19,7 +25,7 @@ pub enum XCapError {
     XcbConnError(#[from] xcb::ConnError),
     #[cfg(target_os = "linux")]
     #[error(transparent)]
-    ImageImageError(#[from] image::ImageError),
+    XcbProtocolError(#[from] xcb::ProtocolError),
     #[cfg(target_os = "linux")]
     #[error(transparent)]
     StdStringFromUtf8Error(#[from] std::string::FromUtf8Error),
@@ -28,9 +34,6 @@ pub enum XCapError {
     ZbusError(#[from] zbus::Error),
     #[cfg(target_os = "linux")]
     #[error(transparent)]
-    StdIOError(#[from] std::io::Error),
-    #[cfg(target_os = "linux")]
-    #[error(transparent)]
     StdTimeSystemTimeError(#[from] std::time::SystemTimeError),
     #[cfg(target_os = "linux")]
     #[error(transparent)]
@@ -1,7 +1,18 @@
+mod change_waiter;
+mod encoder;
 mod error;
 mod monitor;
+mod multi_monitor_recorder;
+mod pipeline;
+mod pixel_format;
+#[cfg(target_os = "linux")]
+mod process_info;
+mod replay_buffer;
+#[cfg(test)]
+mod test_support;
 mod video_recorder;
 mod window;
+mod window_video_recorder;
 
 #[cfg(target_os = "macos")]
 #[path = "macos/mod.rs"]
@@ -17,9 +28,28 @@ mod platform;
 
 pub use image;
 
+pub use encoder::{
+    ApngOptions, ApngWriter, AviOptions, AviWriter, GifOptions, GifWriter, WebmMuxer, WebmTrack,
+    Y4mColorspace, Y4mOptions, Y4mWriter, YuvMatrix,
+};
+#[cfg(feature = "encode")]
+pub use encoder::{Av1Encoder, Av1Options, Av1Packet, IvfWriter};
 pub use error::{XCapError, XCapResult};
 pub use monitor::Monitor;
-pub use window::Window;
+pub use multi_monitor_recorder::MultiMonitorRecorder;
+pub use pipeline::{CropSink, FrameSink, Pipeline, PipelineHandle, ScaleSink, TeeSink};
+pub use pixel_format::PixelFormat;
+#[cfg(target_os = "linux")]
+pub use process_info::ProcessInfo;
+pub use replay_buffer::{ReplayBuffer, ReplayCompression, ReplayOptions};
+pub use window::{FrameExtents, Window, WindowCaptureOptions};
 
+pub use video_recorder::BackpressurePolicy;
+pub use video_recorder::DirtyRect;
 pub use video_recorder::Frame;
+pub use video_recorder::FrameFormat;
+pub use video_recorder::FramePlane;
+pub use video_recorder::RecorderEvent;
+pub use video_recorder::RecorderOptions;
+pub use video_recorder::RecorderState;
 pub use video_recorder::VideoRecorder;
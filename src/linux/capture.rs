@@ -1,13 +1,19 @@
 use image::RgbaImage;
+use xcb::x::{Drawable, GetGeometry, TranslateCoordinates};
 
-use crate::error::XCapResult;
+use crate::{
+    error::{XCapError, XCapResult},
+    window::WindowCaptureOptions,
+};
 
 use super::{
     impl_monitor::ImplMonitor,
-    impl_window::ImplWindow,
-    utils::{get_current_screen_buf, get_monitor_info_buf, wayland_detect},
+    impl_window::{ImplWindow, get_frame_window, get_gtk_frame_extents, get_net_frame_extents},
+    utils::{
+        get_current_screen_buf, get_monitor_info_buf, get_xcb_connection_and_index, wayland_detect,
+    },
     wayland_capture::wayland_capture,
-    xorg_capture::xorg_capture,
+    xorg_capture::{xorg_capture, xorg_composite_capture},
 };
 
 pub fn capture_monitor(impl_monitor: &ImplMonitor) -> XCapResult<RgbaImage> {
@@ -29,6 +35,7 @@ pub fn capture_monitor(impl_monitor: &ImplMonitor) -> XCapResult<RgbaImage> {
             monitor_info_buf.y() as i32,
             monitor_info_buf.width() as u32,
             monitor_info_buf.height() as u32,
+            false,
         )
     }
 }
@@ -53,13 +60,94 @@ pub fn capture_region(
             monitor_info_buf.y() as i32 + y as i32,
             width,
             height,
+            false,
         )
     }
 }
 
-pub fn capture_window(impl_window: &ImplWindow) -> XCapResult<RgbaImage> {
-    let width = impl_window.width()?;
-    let height = impl_window.height()?;
+pub fn capture_window(
+    impl_window: &ImplWindow,
+    options: &WindowCaptureOptions,
+) -> XCapResult<RgbaImage> {
+    let width = impl_window.width()? as i32;
+    let height = impl_window.height()? as i32;
+
+    // 相对于窗口内容区域的截图范围
+    let (mut left, mut top, mut right, mut bottom) = (0, 0, width, height);
+
+    if !options.include_shadow {
+        let gtk_frame_extents = get_gtk_frame_extents(&impl_window.window)?;
+        left += gtk_frame_extents.left;
+        top += gtk_frame_extents.top;
+        right -= gtk_frame_extents.right;
+        bottom -= gtk_frame_extents.bottom;
+    }
+
+    if options.include_decorations {
+        let net_frame_extents = get_net_frame_extents(&impl_window.window)?;
+        left -= net_frame_extents.left;
+        top -= net_frame_extents.top;
+        right += net_frame_extents.right;
+        bottom += net_frame_extents.bottom;
+    }
+
+    // 装饰由窗口管理器绘制在父窗口上，合成后的内容也在顶层的 frame 窗口上
+    let (conn, _) = get_xcb_connection_and_index()?;
+    let frame_window = get_frame_window(&impl_window.window)?;
+
+    let translate_coordinates_cookie = conn.send_request(&TranslateCoordinates {
+        src_window: impl_window.window,
+        dst_window: frame_window,
+        src_x: 0,
+        src_y: 0,
+    });
+    let translate_coordinates_reply = conn.wait_for_reply(translate_coordinates_cookie)?;
+
+    let get_geometry_cookie = conn.send_request(&GetGeometry {
+        drawable: Drawable::Window(frame_window),
+    });
+    let get_geometry_reply = conn.wait_for_reply(get_geometry_cookie)?;
+
+    let offset_x = translate_coordinates_reply.dst_x() as i32;
+    let offset_y = translate_coordinates_reply.dst_y() as i32;
+
+    let frame_left = (offset_x + left).max(0);
+    let frame_top = (offset_y + top).max(0);
+    let frame_right = (offset_x + right).min(get_geometry_reply.width() as i32);
+    let frame_bottom = (offset_y + bottom).min(get_geometry_reply.height() as i32);
+
+    if frame_right <= frame_left || frame_bottom <= frame_top {
+        return Err(XCapError::new("Window frame is empty"));
+    }
+
+    if let Some(rgba_image) = xorg_composite_capture(
+        frame_window,
+        frame_left,
+        frame_top,
+        (frame_right - frame_left) as u32,
+        (frame_bottom - frame_top) as u32,
+        options.preserve_alpha,
+    )? {
+        return Ok(rgba_image);
+    }
+
+    if left >= 0 && top >= 0 && right <= width && bottom <= height {
+        return xorg_capture(
+            impl_window.window,
+            left,
+            top,
+            (right - left).max(0) as u32,
+            (bottom - top).max(0) as u32,
+            options.preserve_alpha,
+        );
+    }
 
-    xorg_capture(impl_window.window, 0, 0, width, height)
+    xorg_capture(
+        frame_window,
+        frame_left,
+        frame_top,
+        (frame_right - frame_left) as u32,
+        (frame_bottom - frame_top) as u32,
+        options.preserve_alpha,
+    )
 }
@@ -1,4 +1,4 @@
-use std::{ffi::CStr, sync::mpsc::Receiver};
+use std::{ffi::CStr, sync::mpsc::Receiver, time::Duration};
 
 use image::RgbaImage;
 use xcb::{
@@ -7,12 +7,13 @@ use xcb::{
         GetCrtcInfo, GetMonitors, GetOutputInfo, GetOutputProperty, GetScreenResources, Mode,
         ModeFlag, ModeInfo, Output, Rotation,
     },
-    x::{ATOM_ANY, ATOM_RESOURCE_MANAGER, ATOM_STRING, CURRENT_TIME, GetProperty},
+    x::{ATOM_ANY, ATOM_RESOURCE_MANAGER, ATOM_STRING, CURRENT_TIME, GetProperty, Rectangle},
 };
 
 use crate::{
+    change_waiter::{ChangeWaiter, PollingWaiter},
     error::{XCapError, XCapResult},
-    video_recorder::Frame,
+    video_recorder::{DirtyRect, Frame, RecorderOptions, RecorderRegion},
 };
 
 use super::{
@@ -22,8 +23,13 @@ use super::{
         get_atom, get_current_screen_buf, get_monitor_info_buf, get_xcb_connection_and_index,
         wayland_detect,
     },
+    xorg_damage::{XorgChangeWaiter, XorgDamage},
 };
 
+/// Every Wayland capture is a full screenshot through the portal or screencopy, so changes
+/// are polled less often than elsewhere
+const WAYLAND_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
 #[derive(Debug, Clone)]
 pub(crate) struct ImplMonitor {
     pub output: Output,
@@ -346,7 +352,7 @@ impl ImplMonitor {
         capture_monitor(self)
     }
 
-    pub fn capture_region(&self, x: u32, y: u32, width: u32, height: u32) -> XCapResult<RgbaImage> {
+    fn check_region(&self, x: u32, y: u32, width: u32, height: u32) -> XCapResult<()> {
         // Validate region bounds
         let monitor_x = self.x()?;
         let monitor_y = self.y()?;
@@ -362,10 +368,71 @@ impl ImplMonitor {
                 "Region ({x}, {y}, {width}, {height}) is outside monitor bounds ({monitor_x}, {monitor_y}, {monitor_width}, {monitor_height})"
             )));
         }
+
+        Ok(())
+    }
+
+    pub fn capture_region(&self, x: u32, y: u32, width: u32, height: u32) -> XCapResult<RgbaImage> {
+        self.check_region(x, y, width, height)?;
         capture_region(self, x, y, width, height)
     }
 
-    pub fn video_recorder(&self) -> XCapResult<(ImplVideoRecorder, Receiver<Frame>)> {
-        ImplVideoRecorder::new(self.clone())
+    pub fn change_waiter(&self, region: &DirtyRect) -> XCapResult<Box<dyn ChangeWaiter>> {
+        self.check_region(region.x, region.y, region.width, region.height)?;
+
+        if wayland_detect() {
+            return Ok(Box::new(PollingWaiter::new(WAYLAND_POLL_INTERVAL)));
+        }
+
+        // 不支持 XDamage 时，退化为定时截图
+        let damage = match XorgDamage::new() {
+            Ok(damage) => damage,
+            Err(err) => {
+                log::debug!("XorgDamage::new failed {err}");
+                return Ok(Box::new(PollingWaiter::default()));
+            }
+        };
+
+        // X11 的矩形坐标只有 16 位
+        let out_of_range = |_| XCapError::new("Region is out of X11 coordinate range");
+        let rectangle = Rectangle {
+            x: i16::try_from(self.x()? + region.x as i32).map_err(out_of_range)?,
+            y: i16::try_from(self.y()? + region.y as i32).map_err(out_of_range)?,
+            width: u16::try_from(region.width).map_err(out_of_range)?,
+            height: u16::try_from(region.height).map_err(out_of_range)?,
+        };
+
+        Ok(Box::new(XorgChangeWaiter::new(damage, rectangle)))
+    }
+
+    pub fn video_recorder(
+        &self,
+        options: &RecorderOptions,
+    ) -> XCapResult<(ImplVideoRecorder, Receiver<Frame>)> {
+        ImplVideoRecorder::new(self.clone(), None, options)
+    }
+
+    pub fn region_video_recorder(
+        &self,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        options: &RecorderOptions,
+    ) -> XCapResult<(ImplVideoRecorder, Receiver<Frame>)> {
+        self.check_region(x, y, width, height)?;
+
+        let region = RecorderRegion {
+            rect: DirtyRect {
+                x,
+                y,
+                width,
+                height,
+            },
+            monitor_width: self.width()?,
+            monitor_height: self.height()?,
+        };
+
+        ImplVideoRecorder::new(self.clone(), Some(region), options)
     }
 }
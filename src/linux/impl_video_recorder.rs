@@ -1,9 +1,16 @@
 use std::sync::mpsc::Receiver;
 
-use crate::{XCapResult, video_recorder::Frame};
+use crate::{
+    XCapResult,
+    video_recorder::{Frame, RecorderEvent, RecorderOptions, RecorderRegion, RecorderState},
+    window_video_recorder::WindowVideoRecorder,
+};
 
 use super::{
-    impl_monitor::ImplMonitor, utils::wayland_detect, wayland_video_recorder::WaylandVideoRecorder,
+    impl_monitor::ImplMonitor,
+    impl_window::ImplWindow,
+    utils::wayland_detect,
+    wayland_video_recorder::{SourceType, WaylandVideoRecorder},
     xorg_video_recorder::XorgVideoRecorder,
 };
 
@@ -11,23 +18,49 @@ use super::{
 pub enum ImplVideoRecorder {
     Xorg(XorgVideoRecorder),
     Wayland(WaylandVideoRecorder),
+    Window(WindowVideoRecorder),
 }
 
 impl ImplVideoRecorder {
-    pub fn new(monitor: ImplMonitor) -> XCapResult<(Self, Receiver<Frame>)> {
+    pub fn new(
+        monitor: ImplMonitor,
+        region: Option<RecorderRegion>,
+        options: &RecorderOptions,
+    ) -> XCapResult<(Self, Receiver<Frame>)> {
         if wayland_detect() {
-            let (recorder, receiver) = WaylandVideoRecorder::new(monitor)?;
+            let (recorder, receiver) = WaylandVideoRecorder::new(
+                SourceType::Monitor,
+                Some(&monitor),
+                region,
+                options.clone(),
+            )?;
             Ok((ImplVideoRecorder::Wayland(recorder), receiver))
         } else {
-            let (recorder, receiver) = XorgVideoRecorder::new(monitor)?;
+            let (recorder, receiver) = XorgVideoRecorder::new(monitor, region, options.clone())?;
             Ok((ImplVideoRecorder::Xorg(recorder), receiver))
         }
     }
 
+    pub fn new_window(
+        window: ImplWindow,
+        options: &RecorderOptions,
+    ) -> XCapResult<(Self, Receiver<Frame>)> {
+        // Wayland 下无法获取其他窗口的内容，由用户在门户中选择窗口
+        if wayland_detect() {
+            let (recorder, receiver) =
+                WaylandVideoRecorder::new(SourceType::Window, None, None, options.clone())?;
+            Ok((ImplVideoRecorder::Wayland(recorder), receiver))
+        } else {
+            let (recorder, receiver) = WindowVideoRecorder::new(window, options.clone())?;
+            Ok((ImplVideoRecorder::Window(recorder), receiver))
+        }
+    }
+
     pub fn start(&self) -> XCapResult<()> {
         match self {
             ImplVideoRecorder::Xorg(recorder) => recorder.start(),
             ImplVideoRecorder::Wayland(recorder) => recorder.start(),
+            ImplVideoRecorder::Window(recorder) => recorder.start(),
         }
     }
 
@@ -35,6 +68,31 @@ impl ImplVideoRecorder {
         match self {
             ImplVideoRecorder::Xorg(recorder) => recorder.stop(),
             ImplVideoRecorder::Wayland(recorder) => recorder.stop(),
+            ImplVideoRecorder::Window(recorder) => recorder.stop(),
+        }
+    }
+
+    pub fn close(&self) -> XCapResult<()> {
+        match self {
+            ImplVideoRecorder::Xorg(recorder) => recorder.close(),
+            ImplVideoRecorder::Wayland(recorder) => recorder.close(),
+            ImplVideoRecorder::Window(recorder) => recorder.close(),
+        }
+    }
+
+    pub fn state(&self) -> XCapResult<RecorderState> {
+        match self {
+            ImplVideoRecorder::Xorg(recorder) => recorder.state(),
+            ImplVideoRecorder::Wayland(recorder) => recorder.state(),
+            ImplVideoRecorder::Window(recorder) => recorder.state(),
+        }
+    }
+
+    pub fn events(&self) -> XCapResult<Receiver<RecorderEvent>> {
+        match self {
+            ImplVideoRecorder::Xorg(recorder) => recorder.events(),
+            ImplVideoRecorder::Wayland(recorder) => recorder.events(),
+            ImplVideoRecorder::Window(recorder) => recorder.events(),
         }
     }
 }
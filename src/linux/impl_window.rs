@@ -1,19 +1,29 @@
+use std::sync::mpsc::Receiver;
+
 use image::RgbaImage;
 use xcb::{
-    Xid,
+    Extension, Xid,
+    res::{ClientIdMask, ClientIdSpec, QueryClientIds},
     x::{
         ATOM_ATOM, ATOM_CARDINAL, ATOM_NONE, ATOM_STRING, ATOM_WM_CLASS, ATOM_WM_NAME, Atom,
-        Drawable, GetGeometry, GetProperty, GetPropertyReply, QueryPointer, TranslateCoordinates,
-        Window,
+        Drawable, GetGeometry, GetProperty, GetPropertyReply, QueryPointer, QueryTree,
+        TranslateCoordinates, Window,
     },
 };
 
-use crate::error::{XCapError, XCapResult};
+use crate::{
+    ProcessInfo,
+    error::{XCapError, XCapResult},
+    video_recorder::{Frame, RecorderOptions},
+    window::{FrameExtents, WindowCaptureOptions},
+};
 
 use super::{
     capture::capture_window,
     impl_monitor::ImplMonitor,
-    utils::{get_atom, get_xcb_connection_and_index},
+    impl_video_recorder::ImplVideoRecorder,
+    process::get_process_info,
+    utils::{get_atom, get_xcb_connection_and_index, has_extension},
 };
 
 #[derive(Debug, Clone)]
@@ -44,16 +54,42 @@ fn get_window_property(
     Ok(window_property_reply)
 }
 
-pub fn get_window_pid(window: &Window) -> XCapResult<u32> {
-    let wm_pid_atom = get_atom("_NET_WM_PID")?;
+/// Query the pid of the client that created the window with the X-Resource extension
+fn get_window_client_pid(window: &Window) -> XCapResult<u32> {
+    if !has_extension(Extension::Res) {
+        return Err(XCapError::new("X-Resource extension not supported"));
+    }
+
+    let (conn, _) = get_xcb_connection_and_index()?;
 
-    let reply = get_window_property(*window, wm_pid_atom, ATOM_CARDINAL, 0, 4)?;
-    let value = reply.value::<u32>();
+    let query_client_ids_cookie = conn.send_request(&QueryClientIds {
+        specs: &[ClientIdSpec {
+            client: window.resource_id(),
+            mask: ClientIdMask::LOCAL_CLIENT_PID,
+        }],
+    });
+    let query_client_ids_reply = conn.wait_for_reply(query_client_ids_cookie)?;
 
-    value
-        .first()
+    query_client_ids_reply
+        .ids()
+        .find(|id| id.spec().mask.contains(ClientIdMask::LOCAL_CLIENT_PID))
+        .and_then(|id| id.value().first().copied())
         .ok_or(XCapError::new("Get window pid failed"))
-        .copied()
+}
+
+pub fn get_window_pid(window: &Window) -> XCapResult<u32> {
+    let wm_pid = get_atom("_NET_WM_PID").and_then(|wm_pid_atom| {
+        let reply = get_window_property(*window, wm_pid_atom, ATOM_CARDINAL, 0, 4)?;
+
+        reply
+            .value::<u32>()
+            .first()
+            .ok_or(XCapError::new("Get window pid failed"))
+            .copied()
+    });
+
+    // _NET_WM_PID 是由客户端自行设置的，可能不存在
+    wm_pid.or_else(|_| get_window_client_pid(window))
 }
 
 fn get_active_window_id() -> XCapResult<u32> {
@@ -96,6 +132,57 @@ fn get_position_and_size(window: &Window) -> XCapResult<(i32, i32, u32, u32)> {
     ))
 }
 
+fn get_window_extents(window: &Window, name: &str) -> XCapResult<FrameExtents> {
+    // 窗口管理器不支持该属性时，视为没有边框
+    let extents_atom = match get_atom(name) {
+        Ok(extents_atom) => extents_atom,
+        Err(_) => return Ok(FrameExtents::default()),
+    };
+
+    let extents_reply = get_window_property(*window, extents_atom, ATOM_CARDINAL, 0, 4)?;
+
+    match extents_reply.value::<u32>() {
+        &[left, right, top, bottom] => Ok(FrameExtents {
+            left: left as i32,
+            right: right as i32,
+            top: top as i32,
+            bottom: bottom as i32,
+        }),
+        _ => Ok(FrameExtents::default()),
+    }
+}
+
+/// Server-side decorations drawn by the window manager
+/// https://specifications.freedesktop.org/wm-spec/1.5/ar01s05.html#id-1.6.19
+pub(super) fn get_net_frame_extents(window: &Window) -> XCapResult<FrameExtents> {
+    get_window_extents(window, "_NET_FRAME_EXTENTS")
+}
+
+/// Client-side shadows drawn by the application inside its window
+pub(super) fn get_gtk_frame_extents(window: &Window) -> XCapResult<FrameExtents> {
+    get_window_extents(window, "_GTK_FRAME_EXTENTS")
+}
+
+/// The top-level ancestor of the window, which is the frame window under reparenting window managers
+pub(super) fn get_frame_window(window: &Window) -> XCapResult<Window> {
+    let (conn, _) = get_xcb_connection_and_index()?;
+
+    let mut frame_window = *window;
+    loop {
+        let query_tree_cookie = conn.send_request(&QueryTree {
+            window: frame_window,
+        });
+        let query_tree_reply = conn.wait_for_reply(query_tree_cookie)?;
+
+        let parent = query_tree_reply.parent();
+        if parent.is_none() || parent == query_tree_reply.root() {
+            return Ok(frame_window);
+        }
+
+        frame_window = parent;
+    }
+}
+
 fn get_window_state(window: &Window) -> XCapResult<(bool, bool)> {
     // https://specifications.freedesktop.org/wm-spec/1.3/ar01s05.html
     let wm_state_atom = get_atom("_NET_WM_STATE")?;
@@ -175,6 +262,10 @@ impl ImplWindow {
         get_window_pid(&self.window)
     }
 
+    pub fn process(&self) -> XCapResult<ProcessInfo> {
+        get_process_info(self.pid()?)
+    }
+
     pub fn app_name(&self) -> XCapResult<String> {
         let get_class_reply =
             get_window_property(self.window, ATOM_WM_CLASS, ATOM_STRING, 0, 1024)?;
@@ -210,7 +301,7 @@ impl ImplWindow {
             // If both are empty, try to get the parent window
             if title.is_empty() {
                 let (conn, _) = get_xcb_connection_and_index()?;
-                let query_tree_cookie = conn.send_request(&xcb::x::QueryTree {
+                let query_tree_cookie = conn.send_request(&QueryTree {
                     window: self.window,
                 });
                 if let Ok(query_tree_reply) = conn.wait_for_reply(query_tree_cookie) {
@@ -321,7 +412,33 @@ impl ImplWindow {
         Ok(active_window_id == self.id()?)
     }
 
+    pub fn frame_extents(&self) -> XCapResult<FrameExtents> {
+        let net_frame_extents = get_net_frame_extents(&self.window)?;
+        let gtk_frame_extents = get_gtk_frame_extents(&self.window)?;
+
+        Ok(FrameExtents {
+            left: net_frame_extents.left - gtk_frame_extents.left,
+            right: net_frame_extents.right - gtk_frame_extents.right,
+            top: net_frame_extents.top - gtk_frame_extents.top,
+            bottom: net_frame_extents.bottom - gtk_frame_extents.bottom,
+        })
+    }
+
     pub fn capture_image(&self) -> XCapResult<RgbaImage> {
-        capture_window(self)
+        capture_window(self, &WindowCaptureOptions::default())
+    }
+
+    pub fn capture_image_with_options(
+        &self,
+        options: &WindowCaptureOptions,
+    ) -> XCapResult<RgbaImage> {
+        capture_window(self, options)
+    }
+
+    pub fn video_recorder(
+        &self,
+        options: &RecorderOptions,
+    ) -> XCapResult<(ImplVideoRecorder, Receiver<Frame>)> {
+        ImplVideoRecorder::new_window(self.clone(), options)
     }
 }
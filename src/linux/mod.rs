@@ -1,8 +1,10 @@
 mod capture;
+mod process;
 pub mod utils;
 mod wayland_capture;
 mod wayland_video_recorder;
 pub mod xorg_capture;
+mod xorg_damage;
 mod xorg_video_recorder;
 
 pub mod impl_monitor;
@@ -0,0 +1,135 @@
+use std::{
+    fs,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    ProcessInfo,
+    error::{XCapError, XCapResult},
+};
+
+/// 系统启动时间，单位为秒
+fn get_boot_time() -> XCapResult<u64> {
+    let stat = fs::read_to_string("/proc/stat")?;
+
+    stat.lines()
+        .find_map(|line| line.strip_prefix("btime "))
+        .ok_or_else(|| XCapError::new("btime not found in /proc/stat"))?
+        .trim()
+        .parse::<u64>()
+        .map_err(XCapError::new)
+}
+
+fn get_clock_ticks() -> u64 {
+    let clock_ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+
+    if clock_ticks > 0 {
+        clock_ticks as u64
+    } else {
+        100
+    }
+}
+
+/// https://man7.org/linux/man-pages/man5/proc_pid_stat.5.html
+/// Returns (name, parent pid, start time in clock ticks after boot)
+fn parse_stat(stat: &str) -> XCapResult<(String, u32, u64)> {
+    // comm 可能包含空格和括号，所以取第一个 '(' 和最后一个 ')' 之间的内容
+    let comm_start = stat
+        .find('(')
+        .ok_or_else(|| XCapError::new("Parse /proc/<pid>/stat failed"))?;
+    let comm_end = stat
+        .rfind(')')
+        .ok_or_else(|| XCapError::new("Parse /proc/<pid>/stat failed"))?;
+
+    let name = stat[comm_start + 1..comm_end].to_string();
+
+    // 从第 3 个字段 state 开始
+    let fields: Vec<&str> = stat[comm_end + 1..].split_whitespace().collect();
+
+    let parent_pid = fields
+        .get(1)
+        .ok_or_else(|| XCapError::new("Parse /proc/<pid>/stat failed"))?
+        .parse::<u32>()
+        .map_err(XCapError::new)?;
+
+    let start_time = fields
+        .get(19)
+        .ok_or_else(|| XCapError::new("Parse /proc/<pid>/stat failed"))?
+        .parse::<u64>()
+        .map_err(XCapError::new)?;
+
+    Ok((name, parent_pid, start_time))
+}
+
+fn parse_uid(status: &str) -> XCapResult<u32> {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("Uid:"))
+        .and_then(|uids| uids.split_whitespace().next())
+        .ok_or_else(|| XCapError::new("Uid not found in /proc/<pid>/status"))?
+        .parse::<u32>()
+        .map_err(XCapError::new)
+}
+
+fn parse_cmdline(cmdline: &[u8]) -> Vec<String> {
+    cmdline
+        .split(|&byte| byte == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| String::from_utf8_lossy(arg).to_string())
+        .collect()
+}
+
+pub fn get_process_info(pid: u32) -> XCapResult<ProcessInfo> {
+    let proc_dir = format!("/proc/{pid}");
+
+    let stat = fs::read_to_string(format!("{proc_dir}/stat"))?;
+    let (name, parent_pid, start_ticks) = parse_stat(&stat)?;
+
+    let status = fs::read_to_string(format!("{proc_dir}/status"))?;
+    let uid = parse_uid(&status)?;
+
+    let cmdline = parse_cmdline(&fs::read(format!("{proc_dir}/cmdline"))?);
+
+    // 其他用户的进程没有权限读取 exe
+    let exe = fs::read_link(format!("{proc_dir}/exe")).ok();
+
+    let start_time = UNIX_EPOCH
+        + Duration::from_secs(get_boot_time()?)
+        + Duration::from_secs_f64(start_ticks as f64 / get_clock_ticks() as f64);
+
+    Ok(ProcessInfo {
+        pid,
+        parent_pid,
+        name,
+        exe,
+        cmdline,
+        start_time: start_time.min(SystemTime::now()),
+        uid,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_stat() {
+        let stat =
+            "1234 (my (odd) app) S 42 1234 1234 0 -1 4194560 100 0 0 0 5 3 0 0 20 0 4 0 987654 0 0";
+        let (name, parent_pid, start_time) = parse_stat(stat).unwrap();
+
+        assert_eq!(name, "my (odd) app");
+        assert_eq!(parent_pid, 42);
+        assert_eq!(start_time, 987654);
+    }
+
+    #[test]
+    fn test_get_current_process_info() {
+        let pid = std::process::id();
+        let process_info = get_process_info(pid).unwrap();
+
+        assert_eq!(process_info.pid, pid);
+        assert!(!process_info.cmdline.is_empty());
+        assert_eq!(process_info.uid, unsafe { libc::getuid() });
+    }
+}
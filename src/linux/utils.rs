@@ -9,7 +9,7 @@ use percent_encoding::percent_decode_str;
 use serde::Deserialize;
 use url::Url;
 use xcb::{
-    ConnResult, Connection as XcbConnection, Xid,
+    ConnResult, Connection as XcbConnection, Extension, Xid,
     randr::{GetMonitors, MonitorInfoBuf, Output},
     x::{Atom, InternAtom, ScreenBuf},
 };
@@ -24,7 +24,11 @@ use crate::{XCapError, error::XCapResult};
 lazy_static! {
     static ref XCB_CONNECTION_AND_INDEX: ConnResult<(XcbConnection, i32)> = {
         let display_name = env::var("DISPLAY").unwrap_or("DISPLAY:1".to_string());
-        XcbConnection::connect(Some(display_name.as_str()))
+        XcbConnection::connect_with_extensions(
+            Some(display_name.as_str()),
+            &[],
+            &[Extension::Composite, Extension::Res, Extension::Shm],
+        )
     };
     static ref ZBUS_CONNECTION: ZBusResult<ZBusConnection> = ZBusConnection::session();
 }
@@ -33,6 +37,13 @@ pub fn get_xcb_connection_and_index() -> XCapResult<&'static (XcbConnection, i32
     XCB_CONNECTION_AND_INDEX.as_ref().map_err(XCapError::new)
 }
 
+/// Whether the X server supports the extension
+pub fn has_extension(extension: Extension) -> bool {
+    get_xcb_connection_and_index()
+        .map(|(conn, _)| conn.active_extensions().any(|item| item == extension))
+        .unwrap_or(false)
+}
+
 pub fn get_zbus_connection() -> XCapResult<&'static ZBusConnection> {
     ZBUS_CONNECTION
         .as_ref()
@@ -1,12 +1,5 @@
 use std::{
-    collections::HashMap,
-    io::Cursor,
-    sync::{
-        Arc,
-        atomic::{AtomicBool, Ordering},
-        mpsc::{self, Receiver, Sender},
-    },
-    thread,
+    collections::HashMap, fmt, io::Cursor, mem, slice, sync::mpsc::Receiver, time::Duration,
 };
 
 use pipewire::{
@@ -15,24 +8,34 @@ use pipewire::{
     main_loop::MainLoop,
     properties,
     spa::{
+        buffer::Data,
         param::{
             ParamType,
             format::{FormatProperties, MediaSubtype, MediaType},
             format_utils,
             video::{VideoFormat, VideoInfoRaw},
         },
-        pod::{self, Pod, serialize::PodSerializer},
-        utils::{Direction, Fraction, Rectangle, SpaTypes},
+        pod::{self, ChoiceValue, Pod, serialize::PodSerializer},
+        sys as spa_sys,
+        utils::{Choice, ChoiceEnum, ChoiceFlags, Direction, Fraction, Id, Rectangle, SpaTypes},
     },
-    stream::{Stream, StreamFlags},
+    stream::{Stream, StreamFlags, StreamState},
 };
+use scopeguard::defer;
 use serde::Deserialize;
 use zbus::{
     blocking::Proxy,
     zvariant::{OwnedFd, OwnedObjectPath, Type, Value},
 };
 
-use crate::{XCapError, XCapResult, video_recorder::Frame};
+use crate::{
+    XCapError, XCapResult,
+    pixel_format::PixelFormat,
+    video_recorder::{
+        DirtyRect, FormatTracker, Frame, FrameInterval, FrameSender, RecorderEvent,
+        RecorderLifecycle, RecorderOptions, RecorderRegion, RecorderState,
+    },
+};
 
 use super::{
     impl_monitor::ImplMonitor,
@@ -58,6 +61,13 @@ pub struct ScreenCastStartResponse {
     pub restore_token: Option<String>,
 }
 
+/// The `types` of SelectSources
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceType {
+    Monitor = 1,
+    Window = 2,
+}
+
 /// https://flatpak.github.io/xdg-desktop-portal/docs/doc-org.freedesktop.portal.ScreenCast.html
 pub struct ScreenCast<'a> {
     proxy: Proxy<'a>,
@@ -105,7 +115,11 @@ impl ScreenCast<'_> {
         Ok(session)
     }
 
-    pub fn select_sources(&self, session: &OwnedObjectPath) -> XCapResult<()> {
+    pub fn select_sources(
+        &self,
+        session: &OwnedObjectPath,
+        source_type: SourceType,
+    ) -> XCapResult<()> {
         let conn = get_zbus_connection()?;
 
         let mut options = HashMap::new();
@@ -114,7 +128,7 @@ impl ScreenCast<'_> {
         let portal_request = get_zbus_portal_request(conn, &handle_token)?;
 
         options.insert("handle_token", Value::from(handle_token));
-        options.insert("types", Value::from(1_u32));
+        options.insert("types", Value::from(source_type as u32));
         options.insert("multiple", Value::from(false));
 
         self.proxy
@@ -140,6 +154,21 @@ impl ScreenCast<'_> {
         wait_zbus_response(&portal_request)
     }
 
+    /// https://flatpak.github.io/xdg-desktop-portal/docs/doc-org.freedesktop.portal.Session.html
+    pub fn close_session(&self, session: &OwnedObjectPath) -> XCapResult<()> {
+        let conn = get_zbus_connection()?;
+        let proxy = Proxy::new(
+            conn,
+            "org.freedesktop.portal.Desktop",
+            session,
+            "org.freedesktop.portal.Session",
+        )?;
+
+        proxy.call_method("Close", &())?;
+
+        Ok(())
+    }
+
     #[allow(dead_code)]
     pub fn open_pipe_wire_remote(&self, session: &OwnedObjectPath) -> XCapResult<OwnedFd> {
         let options: HashMap<&str, Value<'_>> = HashMap::new();
@@ -149,12 +178,287 @@ impl ScreenCast<'_> {
     }
 }
 
+/// The node ID of the picked stream, which must be at `monitor`'s position and size when given
+fn find_stream_id(
+    streams: Option<Vec<(u32, ScreenCastStartStream)>>,
+    monitor: Option<((i32, i32), (i32, i32))>,
+) -> XCapResult<u32> {
+    let (stream_id, stream) = streams
+        .and_then(|streams| streams.into_iter().next())
+        .ok_or(XCapError::new("Stream ID not found"))?;
+
+    // 门户为每个显示器单独弹窗，用户可能选了别的显示器；没有返回的位置和大小无法检查
+    if let Some((position, size)) = monitor
+        && (stream
+            .position
+            .is_some_and(|stream_position| stream_position != position)
+            || stream.size.is_some_and(|stream_size| stream_size != size))
+    {
+        return Err(XCapError::new(format!(
+            "The selected stream at {:?} with size {:?} is not the monitor at {position:?} with size {size:?}",
+            stream.position, stream.size
+        )));
+    }
+
+    Ok(stream_id)
+}
+
+fn serialize_pod(value: pod::Value) -> XCapResult<Vec<u8>> {
+    let values = PodSerializer::serialize(Cursor::new(Vec::new()), &value)
+        .map_err(XCapError::new)?
+        .0
+        .into_inner();
+
+    Ok(values)
+}
+
+/// https://docs.pipewire.org/page_spa_buffer.html
+fn get_meta_param_value(meta_type: u32, size: i32, max_size: i32) -> XCapResult<Vec<u8>> {
+    serialize_pod(pod::Value::Object(pod::Object {
+        type_: SpaTypes::ObjectParamMeta.as_raw(),
+        id: ParamType::Meta.as_raw(),
+        properties: vec![
+            pod::Property::new(spa_sys::SPA_PARAM_META_type, pod::Value::Id(Id(meta_type))),
+            pod::Property::new(
+                spa_sys::SPA_PARAM_META_size,
+                pod::Value::Choice(ChoiceValue::Int(Choice(
+                    ChoiceFlags::empty(),
+                    ChoiceEnum::Range {
+                        default: max_size,
+                        min: size,
+                        max: max_size,
+                    },
+                ))),
+            ),
+        ],
+    }))
+}
+
+fn get_meta_param_values() -> XCapResult<Vec<Vec<u8>>> {
+    let region_size = mem::size_of::<spa_sys::spa_meta_region>() as i32;
+
+    let header_size = mem::size_of::<spa_sys::spa_meta_header>() as i32;
+
+    Ok(vec![
+        get_meta_param_value(spa_sys::SPA_META_Header, header_size, header_size)?,
+        get_meta_param_value(spa_sys::SPA_META_VideoDamage, region_size, region_size * 16)?,
+        get_meta_param_value(spa_sys::SPA_META_VideoCrop, region_size, region_size)?,
+    ])
+}
+
+/// # Safety
+///
+/// `spa_buffer` must be null or point to a buffer dequeued from a stream.
+unsafe fn get_buffer_datas<'a>(spa_buffer: *mut spa_sys::spa_buffer) -> &'a mut [Data] {
+    unsafe {
+        if spa_buffer.is_null() || (*spa_buffer).n_datas == 0 || (*spa_buffer).datas.is_null() {
+            return &mut [];
+        }
+
+        slice::from_raw_parts_mut(
+            (*spa_buffer).datas as *mut Data,
+            (*spa_buffer).n_datas as usize,
+        )
+    }
+}
+
+/// spa_buffer_find_meta 是 inline 函数，没有导出
+///
+/// # Safety
+///
+/// `spa_buffer` must be null or point to a buffer dequeued from a stream.
+unsafe fn find_buffer_meta<'a>(
+    spa_buffer: *const spa_sys::spa_buffer,
+    meta_type: u32,
+) -> Option<&'a spa_sys::spa_meta> {
+    unsafe {
+        if spa_buffer.is_null() || (*spa_buffer).metas.is_null() {
+            return None;
+        }
+
+        slice::from_raw_parts((*spa_buffer).metas, (*spa_buffer).n_metas as usize)
+            .iter()
+            .find(|meta| meta.type_ == meta_type && !meta.data.is_null())
+    }
+}
+
+fn get_framerate_fraction(fps: f32) -> Fraction {
+    Fraction {
+        num: (fps * 1000.0).round() as u32,
+        denom: 1000,
+    }
+}
+
+fn get_pixel_format(video_format: VideoFormat) -> Option<PixelFormat> {
+    match video_format {
+        VideoFormat::RGB => Some(PixelFormat::Rgb),
+        VideoFormat::RGBA => Some(PixelFormat::Rgba),
+        VideoFormat::RGBx => Some(PixelFormat::Rgbx),
+        VideoFormat::BGRA => Some(PixelFormat::Bgra),
+        VideoFormat::BGRx => Some(PixelFormat::Bgrx),
+        VideoFormat::NV12 => Some(PixelFormat::Nv12),
+        VideoFormat::I420 => Some(PixelFormat::I420),
+        _ => None,
+    }
+}
+
+/// 多平面格式的每个平面可能在单独的 data 中，按顺序拼接有效数据
+fn get_buffer_frame_data(datas: &mut [Data]) -> Vec<u8> {
+    let mut frame_data = Vec::new();
+
+    for data in datas.iter_mut() {
+        let offset = data.chunk().offset() as usize;
+        let size = data.chunk().size() as usize;
+
+        let Some(bytes) = data.data() else {
+            continue;
+        };
+
+        let end = if size == 0 {
+            bytes.len()
+        } else {
+            (offset + size).min(bytes.len())
+        };
+
+        if let Some(bytes) = bytes.get(offset..end) {
+            frame_data.extend_from_slice(bytes);
+        }
+    }
+
+    frame_data
+}
+
+/// # Safety
+///
+/// `spa_buffer` must be null or point to a buffer dequeued from a stream.
+unsafe fn get_buffer_dirty_rects(
+    spa_buffer: *const spa_sys::spa_buffer,
+    width: u32,
+    height: u32,
+) -> Vec<DirtyRect> {
+    let Some(meta) = (unsafe { find_buffer_meta(spa_buffer, spa_sys::SPA_META_VideoDamage) })
+    else {
+        return Vec::new();
+    };
+
+    let regions = unsafe {
+        slice::from_raw_parts(
+            meta.data as *const spa_sys::spa_meta_region,
+            meta.size as usize / mem::size_of::<spa_sys::spa_meta_region>(),
+        )
+    };
+
+    // 区域列表以大小为 0 的区域结束
+    regions
+        .iter()
+        .map(|meta_region| meta_region.region)
+        .take_while(|region| region.size.width != 0 && region.size.height != 0)
+        .filter_map(|region| {
+            let x = region.position.x.clamp(0, width as i32) as u32;
+            let y = region.position.y.clamp(0, height as i32) as u32;
+            let right = (region.position.x + region.size.width as i32).clamp(0, width as i32);
+            let bottom = (region.position.y + region.size.height as i32).clamp(0, height as i32);
+
+            if right as u32 <= x || bottom as u32 <= y {
+                return None;
+            }
+
+            Some(DirtyRect {
+                x,
+                y,
+                width: right as u32 - x,
+                height: bottom as u32 - y,
+            })
+        })
+        .collect()
+}
+
+/// The valid part of the buffer, `None` when it is the whole buffer
+///
+/// # Safety
+///
+/// `spa_buffer` must be null or point to a buffer dequeued from a stream.
+unsafe fn get_buffer_video_crop(
+    spa_buffer: *const spa_sys::spa_buffer,
+    width: u32,
+    height: u32,
+) -> Option<DirtyRect> {
+    let meta = unsafe { find_buffer_meta(spa_buffer, spa_sys::SPA_META_VideoCrop) }?;
+    if (meta.size as usize) < mem::size_of::<spa_sys::spa_meta_region>() {
+        return None;
+    }
+
+    let region = unsafe { (*(meta.data as *const spa_sys::spa_meta_region)).region };
+    if region.size.width == 0 || region.size.height == 0 {
+        return None;
+    }
+
+    let crop = DirtyRect {
+        x: region.position.x.max(0) as u32,
+        y: region.position.y.max(0) as u32,
+        width: region.size.width,
+        height: region.size.height,
+    }
+    .intersect(&DirtyRect {
+        x: 0,
+        y: 0,
+        width,
+        height,
+    })?;
+
+    (crop.width != width || crop.height != height).then_some(crop)
+}
+
+/// How long ago the buffer was captured, from its presentation timestamp
+///
+/// # Safety
+///
+/// `spa_buffer` must be null or point to a buffer dequeued from a stream.
+unsafe fn get_buffer_capture_delay(spa_buffer: *const spa_sys::spa_buffer) -> Option<Duration> {
+    let meta = unsafe { find_buffer_meta(spa_buffer, spa_sys::SPA_META_Header) }?;
+    if (meta.size as usize) < mem::size_of::<spa_sys::spa_meta_header>() {
+        return None;
+    }
+
+    let header = unsafe { &*(meta.data as *const spa_sys::spa_meta_header) };
+    if header.pts <= 0 {
+        return None;
+    }
+
+    // pts 使用 CLOCK_MONOTONIC 时钟，单位为纳秒
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    if unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) } != 0 {
+        return None;
+    }
+
+    let now = now.tv_sec as i64 * 1_000_000_000 + now.tv_nsec as i64;
+
+    Some(Duration::from_nanos(
+        now.saturating_sub(header.pts).max(0) as u64
+    ))
+}
+
+/// 在其他线程中退出 PipeWire 主循环
+#[derive(Clone)]
+struct MainLoopQuitter(pipewire::channel::Sender<()>);
+
+impl fmt::Debug for MainLoopQuitter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MainLoopQuitter").finish()
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct WaylandVideoRecorder {
-    #[allow(dead_code)]
-    monitor: ImplMonitor,
-    sender: Sender<Frame>,
-    is_running: Arc<AtomicBool>,
+    region: Option<RecorderRegion>,
+    options: RecorderOptions,
+    sender: FrameSender,
+    session: OwnedObjectPath,
+    lifecycle: RecorderLifecycle,
+    main_loop_quitter: MainLoopQuitter,
 }
 
 #[derive(Clone)]
@@ -163,41 +467,90 @@ struct ListenerUserData {
 }
 
 impl WaylandVideoRecorder {
-    pub fn new(monitor: ImplMonitor) -> XCapResult<(Self, Receiver<Frame>)> {
-        let (sender, receiver) = mpsc::channel();
+    pub fn new(
+        source_type: SourceType,
+        monitor: Option<&ImplMonitor>,
+        region: Option<RecorderRegion>,
+        options: RecorderOptions,
+    ) -> XCapResult<(Self, Receiver<Frame>)> {
+        // 门户返回的位置和大小是逻辑像素
+        let monitor = match monitor {
+            Some(monitor) => Some((
+                (monitor.x()?, monitor.y()?),
+                (monitor.width()? as i32, monitor.height()? as i32),
+            )),
+            None => None,
+        };
+
+        let lifecycle = RecorderLifecycle::new();
+        let (sender, receiver) = FrameSender::new(
+            options.channel_capacity,
+            options.backpressure,
+            lifecycle.events(),
+        );
 
         let screen_cast = ScreenCast::new()?;
         let session = screen_cast.create_session()?;
-        screen_cast.select_sources(&session)?;
-        let response = screen_cast.start(&session)?;
+        screen_cast.select_sources(&session, source_type)?;
 
         // 获取流节点ID
-        let stream_id = response
-            .streams
-            .ok_or(XCapError::new("Stream ID not found"))?
-            .first()
-            .ok_or(XCapError::new("Stream ID not found"))?
-            .0;
+        let stream_id = screen_cast
+            .start(&session)
+            .and_then(|response| find_stream_id(response.streams, monitor));
+        let stream_id = match stream_id {
+            Ok(stream_id) => stream_id,
+            Err(err) => {
+                if let Err(err) = screen_cast.close_session(&session) {
+                    log::error!("Failed to close session: {err:?}");
+                }
+                return Err(err);
+            }
+        };
+
+        let (quit_sender, quit_receiver) = pipewire::channel::channel();
 
         let recorder = Self {
-            monitor,
+            region,
+            options,
             sender,
-            is_running: Arc::new(AtomicBool::new(false)),
+            session,
+            lifecycle,
+            main_loop_quitter: MainLoopQuitter(quit_sender),
         };
 
-        recorder.pipewire_capturer(stream_id)?;
+        if let Err(err) = recorder.pipewire_capturer(stream_id, quit_receiver) {
+            recorder.close()?;
+            return Err(err);
+        }
 
         Ok((recorder, receiver))
     }
 
-    pub fn pipewire_capturer(&self, stream_id: u32) -> XCapResult<()> {
+    fn pipewire_capturer(
+        &self,
+        stream_id: u32,
+        quit_receiver: pipewire::channel::Receiver<()>,
+    ) -> XCapResult<()> {
         let sender = self.sender.clone();
-        let is_running = self.is_running.clone();
+        let lifecycle = self.lifecycle.clone();
+        let options = self.options.clone();
+        let region = self.region;
+        let native_pixel_format = options.native_pixel_format;
+
+        self.lifecycle.spawn(move || {
+            let events = lifecycle.events().clone();
+            let mut sequence = 0;
+            let mut frame_interval = FrameInterval::from_options(&options);
+            let mut skipped_frames = 0;
+            let mut format_tracker = FormatTracker::default();
 
-        thread::spawn(move || {
             pipewire::init();
 
             let main_loop = MainLoop::new(None)?;
+            let _quit_receiver = quit_receiver.attach(main_loop.loop_(), {
+                let main_loop = main_loop.clone();
+                move |_| main_loop.quit()
+            });
             let context = Context::new(&main_loop)?;
             let core = context.connect(None)?;
 
@@ -217,86 +570,245 @@ impl WaylandVideoRecorder {
 
             let _listener = stream
                 .add_local_listener_with_user_data(user_data)
-                .param_changed(|_, user_data, id, param| {
-                    let Some(param) = param else {
-                        return;
-                    };
-
-                    if id != ParamType::Format.as_raw() {
-                        return;
+                .state_changed({
+                    let main_loop = main_loop.clone();
+                    let lifecycle = lifecycle.clone();
+                    let mut has_streamed = false;
+
+                    move |_, _, _, new_state| match new_state {
+                        StreamState::Streaming => has_streamed = true,
+                        StreamState::Error(err) => {
+                            log::error!("Stream error: {err}");
+                            lifecycle.events().error(XCapError::new(err));
+                            lifecycle.fail();
+                            main_loop.quit();
+                        }
+                        // 开始推流后断开，说明合成器关闭了共享
+                        StreamState::Unconnected if has_streamed => {
+                            if !matches!(lifecycle.state(), Ok(RecorderState::Closed)) {
+                                lifecycle.events().emit(RecorderEvent::SourceClosed);
+                                lifecycle.fail();
+                            }
+                            main_loop.quit();
+                        }
+                        _ => {}
                     }
+                })
+                .param_changed({
+                    let events = events.clone();
 
-                    let (media_type, media_subtype) = match format_utils::parse_format(param) {
-                        Ok(v) => v,
-                        Err(err) => {
+                    move |stream, user_data, id, param| {
+                        let Some(param) = param else {
+                            return;
+                        };
+
+                        if id != ParamType::Format.as_raw() {
+                            return;
+                        }
+
+                        let (media_type, media_subtype) = match format_utils::parse_format(param) {
+                            Ok(v) => v,
+                            Err(err) => {
+                                log::error!("Failed to parse format: {err:?}");
+                                return;
+                            }
+                        };
+
+                        if media_type != MediaType::Video || media_subtype != MediaSubtype::Raw {
+                            return;
+                        }
+
+                        if let Err(err) = user_data.format.parse(param) {
                             log::error!("Failed to parse format: {err:?}");
                             return;
                         }
-                    };
 
-                    if media_type != MediaType::Video || media_subtype != MediaSubtype::Raw {
-                        return;
-                    }
+                        // 转换、裁剪和缩放后的格式在发送帧时报告
+                        if get_pixel_format(user_data.format.format()).is_none() {
+                            events.error(XCapError::new(format!(
+                                "Unsupported format: {:?}",
+                                user_data.format.format()
+                            )));
+                        }
 
-                    if let Err(err) = user_data.format.parse(param) {
-                        log::error!("Failed to parse format: {err:?}");
+                        // 格式协商完成后，请求需要的 buffer metadata
+                        let meta_values = match get_meta_param_values() {
+                            Ok(meta_values) => meta_values,
+                            Err(err) => {
+                                log::error!("Failed to create meta params: {err:?}");
+                                return;
+                            }
+                        };
+                        let mut meta_params: Vec<&Pod> = meta_values
+                            .iter()
+                            .filter_map(|meta_value| Pod::from_bytes(meta_value))
+                            .collect();
+
+                        if let Err(err) = stream.update_params(&mut meta_params) {
+                            log::error!("Failed to update params: {err:?}");
+                        }
                     }
                 })
                 .process(move |stream, user_data| {
-                    let state = is_running.load(Ordering::Relaxed);
-                    if !state {
+                    if !matches!(lifecycle.state(), Ok(RecorderState::Running)) {
+                        return;
+                    }
+
+                    // 需要访问 buffer 的 metadata，所以直接使用原始 buffer
+                    let raw_buffer = unsafe { stream.dequeue_raw_buffer() };
+                    if raw_buffer.is_null() {
+                        log::info!("stream.dequeue_raw_buffer() returned null");
+                        return;
+                    }
+                    defer! {
+                        unsafe { stream.queue_raw_buffer(raw_buffer) };
+                    }
+
+                    // 合成器不一定遵守协商的帧率，超出 max_fps 的帧直接丢弃
+                    if !frame_interval.ready() {
+                        skipped_frames += 1;
+                        return;
+                    }
+
+                    let spa_buffer = unsafe { (*raw_buffer).buffer };
+                    let datas = unsafe { get_buffer_datas(spa_buffer) };
+                    if datas.is_empty() {
                         return;
                     }
-                    match stream.dequeue_buffer() {
-                        None => log::info!("stream.dequeue_buffer() returned None"),
-                        Some(mut buffer) => {
-                            let datas = buffer.datas_mut();
-                            if datas.is_empty() {
+                    let size = user_data.format.size();
+                    let Some(pixel_format) = get_pixel_format(user_data.format.format()) else {
+                        log::error!("Unsupported format: {:?}", user_data.format.format());
+                        return;
+                    };
+
+                    let stride = datas[0].chunk().stride();
+                    let frame_data = get_buffer_frame_data(datas);
+                    if frame_data.is_empty() {
+                        return;
+                    }
+
+                    // 跳过的帧的变化区域已经丢失，只能认为整帧都变化了
+                    let dirty_rects = if skipped_frames > 0 {
+                        events.emit(RecorderEvent::FramesDropped(skipped_frames));
+                        Vec::new()
+                    } else {
+                        unsafe { get_buffer_dirty_rects(spa_buffer, size.width, size.height) }
+                    };
+                    skipped_frames = 0;
+
+                    let mut frame = Frame::new(size.width, size.height, frame_data)
+                        .with_pixel_format(pixel_format)
+                        .with_dirty_rects(dirty_rects)
+                        .with_sequence(sequence);
+
+                    if stride > 0 {
+                        frame = frame.with_stride(stride as u32);
+                    }
+
+                    // 先去掉合成器标记的无效区域，再裁剪出需要录制的区域
+                    if let Some(crop) =
+                        unsafe { get_buffer_video_crop(spa_buffer, size.width, size.height) }
+                    {
+                        frame = match frame.crop(crop.x, crop.y, crop.width, crop.height) {
+                            Ok(frame) => frame,
+                            Err(err) => {
+                                log::error!("Failed to crop frame: {err:?}");
+                                events.error(err);
                                 return;
                             }
-                            let size = user_data.format.size();
-                            if let Some(frame_data) = datas[0].data() {
-                                let buffer = match user_data.format.format() {
-                                    VideoFormat::RGB => {
-                                        let mut buf =
-                                            vec![0; (size.width * size.height * 4) as usize];
-                                        for (src, dst) in
-                                            frame_data.chunks_exact(3).zip(buf.chunks_exact_mut(4))
-                                        {
-                                            dst[0] = src[0];
-                                            dst[1] = src[1];
-                                            dst[2] = src[2];
-                                            dst[3] = 255;
-                                        }
-
-                                        buf
-                                    }
-                                    VideoFormat::RGBA => frame_data.to_vec(),
-                                    VideoFormat::RGBx => frame_data.to_vec(),
-                                    VideoFormat::BGRx => {
-                                        let mut buf = frame_data.to_vec();
-                                        for src in buf.chunks_exact_mut(4) {
-                                            src.swap(0, 2);
-                                        }
-
-                                        buf
-                                    }
-                                    _ => {
-                                        log::error!(
-                                            "Unsupported format: {:?}",
-                                            user_data.format.format()
-                                        );
-                                        return;
-                                    }
-                                };
-
-                                let _ = sender.send(Frame::new(size.width, size.height, buffer));
+                        };
+                    }
+
+                    if let Some(region) = region {
+                        frame = match region.crop(&frame) {
+                            Ok(Some(frame)) => frame,
+                            // 区域内没有变化则不发送帧
+                            Ok(None) => return,
+                            Err(err) => {
+                                log::error!("Failed to crop frame: {err:?}");
+                                events.error(err);
+                                return;
                             }
+                        };
+                    }
+
+                    // 裁剪后再转换，只转换需要的部分；非原始格式输出时，同时去掉行填充
+                    if !native_pixel_format
+                        && (frame.pixel_format != PixelFormat::Rgba
+                            || frame.stride != frame.width * 4)
+                    {
+                        frame = match frame.convert(PixelFormat::Rgba) {
+                            Ok(frame) => frame,
+                            Err(err) => {
+                                log::error!("Failed to convert frame: {err:?}");
+                                events.error(err);
+                                return;
+                            }
+                        };
+                    }
+
+                    if let Some(delay) = unsafe { get_buffer_capture_delay(spa_buffer) } {
+                        frame = frame.with_capture_delay(delay);
+                    }
+
+                    let frame = match options.scale_frame(frame) {
+                        Ok(frame) => frame,
+                        Err(err) => {
+                            log::error!("Failed to scale frame: {err:?}");
+                            events.error(err);
+                            return;
                         }
+                    };
+
+                    let framerate = user_data.format.framerate();
+                    let fps = (framerate.num > 0 && framerate.denom > 0)
+                        .then_some(framerate.num as f32 / framerate.denom as f32);
+                    format_tracker.update(&frame, fps, &events);
+
+                    if sender.send(frame).is_ok() {
+                        sequence += 1;
                     }
                 })
                 .register()?;
 
+            // 原始格式输出时，也接受需要转换的格式
+            let mut video_formats = vec![
+                VideoFormat::RGB,
+                VideoFormat::RGBA,
+                VideoFormat::RGBx,
+                VideoFormat::BGRx,
+            ];
+            if native_pixel_format {
+                video_formats.extend([VideoFormat::BGRA, VideoFormat::NV12, VideoFormat::I420]);
+            }
+
+            let default_size = match options.output_size {
+                Some((width, height)) => Rectangle { width, height },
+                None => Rectangle {
+                    width: 128,
+                    height: 128,
+                },
+            };
+            let max_size = Rectangle {
+                width: default_size.width.max(4096),
+                height: default_size.height.max(4096),
+            };
+
+            // 默认帧率不能超过最大帧率
+            let default_fps = options.target_fps.unwrap_or(24.0);
+            let default_framerate = get_framerate_fraction(
+                options
+                    .max_fps
+                    .map_or(default_fps, |max_fps| default_fps.min(max_fps)),
+            );
+            let max_framerate = options.max_fps.map_or(
+                Fraction {
+                    num: 1000,
+                    denom: 1,
+                },
+                get_framerate_fraction,
+            );
+
             let obj = pod::object!(
                 SpaTypes::ObjectParamFormat,
                 ParamType::EnumFormat,
@@ -304,52 +816,40 @@ impl WaylandVideoRecorder {
                 pod::property!(FormatProperties::MediaSubtype, Id, MediaSubtype::Raw),
                 pod::property!(
                     FormatProperties::VideoFormat,
-                    Choice,
-                    Enum,
-                    Id,
-                    VideoFormat::RGB,
-                    VideoFormat::RGBA,
-                    VideoFormat::RGBx,
-                    VideoFormat::BGRx,
-                    // VideoFormat::YUY2,
-                    // VideoFormat::I420,
+                    pod::Value::Choice(ChoiceValue::Id(Choice(
+                        ChoiceFlags::empty(),
+                        ChoiceEnum::Enum {
+                            default: Id(VideoFormat::RGB.as_raw()),
+                            alternatives: video_formats
+                                .iter()
+                                .map(|video_format| Id(video_format.as_raw()))
+                                .collect(),
+                        },
+                    )))
                 ),
                 pod::property!(
                     FormatProperties::VideoSize,
                     Choice,
                     Range,
                     Rectangle,
-                    Rectangle {
-                        width: 128,
-                        height: 128
-                    },
+                    default_size,
                     Rectangle {
                         width: 1,
                         height: 1
                     },
-                    Rectangle {
-                        width: 4096,
-                        height: 4096
-                    }
+                    max_size
                 ),
                 pod::property!(
                     FormatProperties::VideoFramerate,
                     Choice,
                     Range,
                     Fraction,
-                    Fraction { num: 24, denom: 1 },
+                    default_framerate,
                     Fraction { num: 0, denom: 1 },
-                    Fraction {
-                        num: 1000,
-                        denom: 1
-                    }
+                    max_framerate
                 ),
             );
-            let values =
-                PodSerializer::serialize(Cursor::new(Vec::new()), &pod::Value::Object(obj))
-                    .map_err(XCapError::new)?
-                    .0
-                    .into_inner();
+            let values = serialize_pod(pod::Value::Object(obj))?;
 
             let mut params =
                 [Pod::from_bytes(&values).ok_or(XCapError::new("Failed to create Pod"))?];
@@ -363,19 +863,37 @@ impl WaylandVideoRecorder {
 
             main_loop.run();
 
-            Result::<(), XCapError>::Ok(())
-        });
-
-        Ok(())
+            Ok(())
+        })
     }
 
     pub fn start(&self) -> XCapResult<()> {
-        self.is_running.store(true, Ordering::Relaxed);
+        self.lifecycle.start()?;
         Ok(())
     }
 
     pub fn stop(&self) -> XCapResult<()> {
-        self.is_running.store(false, Ordering::Relaxed);
-        Ok(())
+        self.lifecycle.stop()
+    }
+
+    pub fn close(&self) -> XCapResult<()> {
+        if !self.lifecycle.close()? {
+            return Ok(());
+        }
+
+        self.sender.close();
+        // 主循环已经退出时发送会失败，可以忽略
+        let _ = self.main_loop_quitter.0.send(());
+        self.lifecycle.join()?;
+
+        ScreenCast::new()?.close_session(&self.session)
+    }
+
+    pub fn state(&self) -> XCapResult<RecorderState> {
+        self.lifecycle.state()
+    }
+
+    pub fn events(&self) -> XCapResult<Receiver<RecorderEvent>> {
+        self.lifecycle.events().subscribe()
     }
 }
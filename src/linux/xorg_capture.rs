@@ -1,10 +1,27 @@
+use std::{
+    ffi::c_void,
+    io, ptr, slice,
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
 use image::RgbaImage;
+use scopeguard::defer;
 use xcb::{
-    Connection,
-    x::{Drawable, GetImage, ImageFormat, ImageOrder, Window},
+    Connection, Extension,
+    composite::{self, NameWindowPixmap, Redirect, RedirectWindow, UnredirectWindow},
+    shm::{self, Attach, Detach, Seg},
+    x::{Drawable, FreePixmap, GetImage, ImageFormat, ImageOrder, Pixmap, Window},
 };
 
-use crate::error::{XCapError, XCapResult};
+use crate::{
+    error::{XCapError, XCapResult},
+    pixel_format::PixelFormat,
+};
+
+use super::utils::{get_xcb_connection_and_index, has_extension};
 
 fn get_pixel8_rgba(
     bytes: &[u8],
@@ -69,30 +86,51 @@ fn get_pixel24_32_rgba(
     }
 }
 
-pub fn xorg_capture(
-    window: Window,
-    x: i32,
-    y: i32,
+fn get_pixel32_argb(
+    bytes: &[u8],
+    x: u32,
+    y: u32,
     width: u32,
-    height: u32,
-) -> XCapResult<RgbaImage> {
-    let (conn, _) = Connection::connect(None)?;
+    bits_per_pixel: u32,
+    bit_order: ImageOrder,
+) -> (u8, u8, u8, u8) {
+    let index = ((y * width + x) * bits_per_pixel / 8) as usize;
 
-    let setup = conn.get_setup();
+    let (r, g, b, a) = if bit_order == ImageOrder::LsbFirst {
+        (
+            bytes[index + 2],
+            bytes[index + 1],
+            bytes[index],
+            bytes[index + 3],
+        )
+    } else {
+        (
+            bytes[index + 1],
+            bytes[index + 2],
+            bytes[index + 3],
+            bytes[index],
+        )
+    };
 
-    let get_image_cookie = conn.send_request(&GetImage {
-        format: ImageFormat::ZPixmap,
-        drawable: Drawable::Window(window),
-        x: x as i16,
-        y: y as i16,
-        width: width as u16,
-        height: height as u16,
-        plane_mask: u32::MAX,
-    });
+    if a == 0 {
+        return (0, 0, 0, 0);
+    }
 
-    let get_image_reply = conn.wait_for_reply(get_image_cookie)?;
-    let bytes = get_image_reply.data();
-    let depth = get_image_reply.depth();
+    // ARGB visual 中的颜色是预乘 alpha 的，需要还原
+    let unpremultiply = |c: u8| ((c as u32 * 255 + a as u32 / 2) / a as u32).min(255) as u8;
+
+    (unpremultiply(r), unpremultiply(g), unpremultiply(b), a)
+}
+
+fn to_rgba_image(
+    conn: &Connection,
+    bytes: &[u8],
+    depth: u8,
+    width: u32,
+    height: u32,
+    preserve_alpha: bool,
+) -> XCapResult<RgbaImage> {
+    let setup = conn.get_setup();
 
     let pixmap_format = setup
         .pixmap_formats()
@@ -107,6 +145,7 @@ pub fn xorg_capture(
         8 => get_pixel8_rgba,
         16 => get_pixel16_rgba,
         24 => get_pixel24_32_rgba,
+        32 if preserve_alpha => get_pixel32_argb,
         32 => get_pixel24_32_rgba,
         _ => return Err(XCapError::new(format!("Unsupported {depth} depth"))),
     };
@@ -127,3 +166,333 @@ pub fn xorg_capture(
     RgbaImage::from_raw(width, height, rgba)
         .ok_or_else(|| XCapError::new("RgbaImage::from_raw failed"))
 }
+
+/// A System V shared memory segment attached to the X server
+///
+/// The segment id only exists on the connection it was attached on.
+struct ShmSegment {
+    conn: &'static Connection,
+    shmseg: Seg,
+    addr: *mut u8,
+    size: usize,
+}
+
+unsafe impl Send for ShmSegment {}
+
+impl ShmSegment {
+    fn new(conn: &'static Connection, size: usize) -> XCapResult<ShmSegment> {
+        unsafe {
+            let shmid = libc::shmget(libc::IPC_PRIVATE, size, libc::IPC_CREAT | 0o600);
+            if shmid == -1 {
+                return Err(io::Error::last_os_error().into());
+            }
+
+            let addr = libc::shmat(shmid, ptr::null(), 0);
+            if addr as isize == -1 {
+                let err = io::Error::last_os_error();
+                libc::shmctl(shmid, libc::IPC_RMID, ptr::null_mut());
+                return Err(err.into());
+            }
+
+            let shmseg: Seg = conn.generate_id();
+            let attach_result = conn.send_and_check_request(&Attach {
+                shmseg,
+                shmid: shmid as u32,
+                read_only: false,
+            });
+
+            // 标记删除，双方都 detach 之后由系统释放
+            libc::shmctl(shmid, libc::IPC_RMID, ptr::null_mut());
+
+            if let Err(err) = attach_result {
+                // 服务端拒绝 attach（例如远程连接），之后不再尝试共享内存
+                SHM_UNAVAILABLE.store(true, Ordering::Relaxed);
+                libc::shmdt(addr);
+                return Err(err.into());
+            }
+
+            Ok(ShmSegment {
+                conn,
+                shmseg,
+                addr: addr as *mut u8,
+                size,
+            })
+        }
+    }
+}
+
+impl Drop for ShmSegment {
+    fn drop(&mut self) {
+        let _ = self.conn.send_and_check_request(&Detach {
+            shmseg: self.shmseg,
+        });
+
+        unsafe {
+            libc::shmdt(self.addr as *const c_void);
+        }
+    }
+}
+
+// 复用同一个共享内存段，避免每次截图都重新分配
+static SHM_SEGMENT: Mutex<Option<ShmSegment>> = Mutex::new(None);
+
+// 远程连接等情况下服务端拒绝 attach，之后不再尝试
+static SHM_UNAVAILABLE: AtomicBool = AtomicBool::new(false);
+
+fn shm_get_image<T>(
+    conn: &'static Connection,
+    drawable: Drawable,
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+    convert: &impl Fn(&[u8], u8) -> XCapResult<T>,
+) -> XCapResult<T> {
+    // 最大按照每个像素 4 字节计算
+    let size = (width * height * 4) as usize;
+
+    // 取出共享内存段后立即释放锁，不在等待服务端回复时持有
+    let cached = SHM_SEGMENT.lock()?.take();
+    let shm_segment = match cached {
+        Some(segment) if segment.size >= size && ptr::eq(segment.conn, conn) => segment,
+        _ => ShmSegment::new(conn, size)?,
+    };
+
+    let get_image_cookie = conn.send_request(&shm::GetImage {
+        drawable,
+        x: x as i16,
+        y: y as i16,
+        width: width as u16,
+        height: height as u16,
+        plane_mask: u32::MAX,
+        format: ImageFormat::ZPixmap as u8,
+        shmseg: shm_segment.shmseg,
+        offset: 0,
+    });
+    let get_image_reply = conn.wait_for_reply(get_image_cookie)?;
+
+    let bytes = unsafe {
+        slice::from_raw_parts(
+            shm_segment.addr,
+            (get_image_reply.size() as usize).min(shm_segment.size),
+        )
+    };
+
+    let result = convert(bytes, get_image_reply.depth());
+
+    // 放回缓存，并发截图时保留较大的那个，被替换的段在锁外释放
+    let evicted = {
+        let mut cached = SHM_SEGMENT.lock()?;
+        match cached.take() {
+            Some(other) if other.size > shm_segment.size => {
+                *cached = Some(other);
+                Some(shm_segment)
+            }
+            other => {
+                *cached = Some(shm_segment);
+                other
+            }
+        }
+    };
+    drop(evicted);
+
+    result
+}
+
+/// 获取图像数据，由 `convert` 根据色深转换为需要的格式
+fn get_image_with<T>(
+    conn: &'static Connection,
+    drawable: Drawable,
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+    convert: impl Fn(&[u8], u8) -> XCapResult<T>,
+) -> XCapResult<T> {
+    if has_extension(Extension::Shm) && !SHM_UNAVAILABLE.load(Ordering::Relaxed) {
+        match shm_get_image(conn, drawable, x, y, width, height, &convert) {
+            Ok(image) => return Ok(image),
+            Err(err) => log::debug!("shm_get_image failed {err}"),
+        }
+    }
+
+    let get_image_cookie = conn.send_request(&GetImage {
+        format: ImageFormat::ZPixmap,
+        drawable,
+        x: x as i16,
+        y: y as i16,
+        width: width as u16,
+        height: height as u16,
+        plane_mask: u32::MAX,
+    });
+
+    let get_image_reply = conn.wait_for_reply(get_image_cookie)?;
+
+    convert(get_image_reply.data(), get_image_reply.depth())
+}
+
+fn get_image(
+    conn: &'static Connection,
+    drawable: Drawable,
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+    preserve_alpha: bool,
+) -> XCapResult<RgbaImage> {
+    get_image_with(conn, drawable, x, y, width, height, |bytes, depth| {
+        to_rgba_image(conn, bytes, depth, width, height, preserve_alpha)
+    })
+}
+
+/// 24/32 位色深且字节序为 LSBFirst 时，ZPixmap 数据就是 BGRx，直接使用
+fn to_native_frame_data(
+    conn: &Connection,
+    bytes: &[u8],
+    depth: u8,
+    width: u32,
+    height: u32,
+) -> XCapResult<(Vec<u8>, PixelFormat)> {
+    let setup = conn.get_setup();
+    let size = (width * height * 4) as usize;
+
+    let is_bgrx = (depth == 24 || depth == 32)
+        && setup.image_byte_order() == ImageOrder::LsbFirst
+        && setup
+            .pixmap_formats()
+            .iter()
+            .any(|item| item.depth() == depth && item.bits_per_pixel() == 32)
+        && bytes.len() >= size;
+
+    if is_bgrx {
+        return Ok((bytes[..size].to_vec(), PixelFormat::Bgrx));
+    }
+
+    let rgba_image = to_rgba_image(conn, bytes, depth, width, height, false)?;
+
+    Ok((rgba_image.into_raw(), PixelFormat::Rgba))
+}
+
+pub fn xorg_capture(
+    window: Window,
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+    preserve_alpha: bool,
+) -> XCapResult<RgbaImage> {
+    let (conn, _) = get_xcb_connection_and_index()?;
+
+    get_image(
+        conn,
+        Drawable::Window(window),
+        x,
+        y,
+        width,
+        height,
+        preserve_alpha,
+    )
+}
+
+/// Capture a window area as BGRx when the server's pixel layout allows it, as RGBA otherwise.
+pub fn xorg_capture_native(
+    window: Window,
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+) -> XCapResult<(Vec<u8>, PixelFormat)> {
+    let (conn, _) = get_xcb_connection_and_index()?;
+
+    get_image_with(
+        conn,
+        Drawable::Window(window),
+        x,
+        y,
+        width,
+        height,
+        |bytes, depth| to_native_frame_data(conn, bytes, depth, width, height),
+    )
+}
+
+/// Capture the off-screen pixmap of a top-level window with the Composite extension,
+/// so the window's own contents are returned even when it is covered by other windows.
+///
+/// Returns `None` when the Composite extension is missing or the window can't be redirected,
+/// callers then fall back to GetImage.
+pub fn xorg_composite_capture(
+    window: Window,
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+    preserve_alpha: bool,
+) -> XCapResult<Option<RgbaImage>> {
+    if !has_extension(Extension::Composite) {
+        return Ok(None);
+    }
+
+    let (conn, _) = get_xcb_connection_and_index()?;
+
+    // NameWindowPixmap 需要 0.2 及以上版本
+    let query_version_cookie = conn.send_request(&composite::QueryVersion {
+        client_major_version: 0,
+        client_minor_version: 4,
+    });
+    let query_version_reply = conn.wait_for_reply(query_version_cookie)?;
+    if query_version_reply.major_version() == 0 && query_version_reply.minor_version() < 2 {
+        return Ok(None);
+    }
+
+    // 自动重定向可以和合成管理器的手动重定向共存，截图后撤销
+    if let Err(err) = conn.send_and_check_request(&RedirectWindow {
+        window,
+        update: Redirect::Automatic,
+    }) {
+        log::debug!("RedirectWindow failed {err}");
+        return Ok(None);
+    }
+    defer!({
+        let _ = conn.send_and_check_request(&UnredirectWindow {
+            window,
+            update: Redirect::Automatic,
+        });
+    });
+
+    let pixmap: Pixmap = conn.generate_id();
+    conn.send_and_check_request(&NameWindowPixmap { window, pixmap })?;
+    defer!({
+        let _ = conn.send_and_check_request(&FreePixmap { pixmap });
+    });
+
+    get_image(
+        conn,
+        Drawable::Pixmap(pixmap),
+        x,
+        y,
+        width,
+        height,
+        preserve_alpha,
+    )
+    .map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_pixel32_argb_unpremultiply() {
+        // 50% 透明的纯红色，预乘后为 (128, 0, 0, 128)，LSB 字节序为 BGRA
+        let bytes = [0, 0, 128, 128, 0, 0, 0, 0];
+
+        assert_eq!(
+            get_pixel32_argb(&bytes, 0, 0, 2, 32, ImageOrder::LsbFirst),
+            (255, 0, 0, 128)
+        );
+        assert_eq!(
+            get_pixel32_argb(&bytes, 1, 0, 2, 32, ImageOrder::LsbFirst),
+            (0, 0, 0, 0)
+        );
+    }
+}
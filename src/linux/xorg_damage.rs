@@ -0,0 +1,201 @@
+use std::{
+    cell::Cell,
+    io,
+    os::fd::AsRawFd,
+    time::{Duration, Instant},
+};
+
+use xcb::{
+    Connection, Event, Extension, Xid,
+    damage::{self, Damage, ReportLevel},
+    x::{Drawable, Rectangle},
+    xfixes::{self, CreateRegion, DestroyRegion, FetchRegion, Region},
+};
+
+use crate::{
+    change_waiter::ChangeWaiter,
+    error::{XCapError, XCapResult},
+};
+
+/// Tracks changes on the root window with the XDamage extension.
+///
+/// Uses its own connection, so damage events are not mixed with other requests.
+pub struct XorgDamage {
+    conn: Connection,
+    damage: Damage,
+    region: Region,
+    /// A damage event was taken from the queue by [`XorgDamage::wait_for_event`]
+    notified: Cell<bool>,
+}
+
+impl XorgDamage {
+    pub fn new() -> XCapResult<XorgDamage> {
+        let (conn, index) = Connection::connect_with_extensions(
+            None,
+            &[],
+            &[Extension::Damage, Extension::XFixes],
+        )?;
+
+        let has_extension = |extension| conn.active_extensions().any(|item| item == extension);
+        if !has_extension(Extension::Damage) || !has_extension(Extension::XFixes) {
+            return Err(XCapError::new("Damage extension not supported"));
+        }
+
+        // 使用扩展之前必须先协商版本
+        let xfixes_query_version_cookie = conn.send_request(&xfixes::QueryVersion {
+            client_major_version: 2,
+            client_minor_version: 0,
+        });
+        conn.wait_for_reply(xfixes_query_version_cookie)?;
+
+        let damage_query_version_cookie = conn.send_request(&damage::QueryVersion {
+            client_major_version: 1,
+            client_minor_version: 1,
+        });
+        conn.wait_for_reply(damage_query_version_cookie)?;
+
+        let root = conn
+            .get_setup()
+            .roots()
+            .nth(index as usize)
+            .ok_or_else(|| XCapError::new("Not found screen"))?
+            .root();
+
+        let region: Region = conn.generate_id();
+        conn.send_and_check_request(&CreateRegion {
+            region,
+            rectangles: &[],
+        })?;
+
+        let damage: Damage = conn.generate_id();
+        // NonEmpty 只在区域从空变为非空时通知一次，具体区域在 Subtract 时获取
+        if let Err(err) = conn.send_and_check_request(&damage::Create {
+            damage,
+            drawable: Drawable::Window(root),
+            level: ReportLevel::NonEmpty,
+        }) {
+            let _ = conn.send_and_check_request(&DestroyRegion { region });
+            return Err(err.into());
+        }
+
+        Ok(XorgDamage {
+            conn,
+            damage,
+            region,
+            notified: Cell::new(false),
+        })
+    }
+
+    /// The rectangles of the root window damaged since the last call, in root coordinates.
+    /// Returns an empty list if nothing has changed.
+    pub fn damaged_rectangles(&self) -> XCapResult<Vec<Rectangle>> {
+        let mut is_damaged = self.notified.replace(false);
+
+        while let Some(event) = self.conn.poll_for_event()? {
+            if let Event::Damage(damage::Event::Notify(_)) = event {
+                is_damaged = true;
+            }
+        }
+
+        if !is_damaged {
+            return Ok(Vec::new());
+        }
+
+        self.conn.send_and_check_request(&damage::Subtract {
+            damage: self.damage,
+            repair: Region::none(),
+            parts: self.region,
+        })?;
+
+        let fetch_region_cookie = self.conn.send_request(&FetchRegion {
+            region: self.region,
+        });
+        let fetch_region_reply = self.conn.wait_for_reply(fetch_region_cookie)?;
+
+        Ok(fetch_region_reply.rectangles().to_vec())
+    }
+
+    /// Block until a damage event may have arrived, or after `timeout`.
+    pub fn wait_for_event(&self, timeout: Duration) -> XCapResult<()> {
+        // 等待回复时读到的事件已在 xcb 队列中，fd 不会再变为可读
+        while let Some(event) = self.conn.poll_for_queued_event()? {
+            if let Event::Damage(damage::Event::Notify(_)) = event {
+                self.notified.set(true);
+            }
+        }
+        if self.notified.get() {
+            return Ok(());
+        }
+
+        let mut pollfd = libc::pollfd {
+            fd: self.conn.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // 向上取整，避免超时前反复以 0 毫秒返回
+        let timeout_ms = timeout.as_micros().div_ceil(1000).min(i32::MAX as u128) as i32;
+        if unsafe { libc::poll(&mut pollfd, 1, timeout_ms) } < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err.into());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for XorgDamage {
+    fn drop(&mut self) {
+        let _ = self.conn.send_and_check_request(&damage::Destroy {
+            damage: self.damage,
+        });
+        let _ = self.conn.send_and_check_request(&DestroyRegion {
+            region: self.region,
+        });
+    }
+}
+
+/// Wakes up only when the damage overlaps a rectangle of the root window.
+pub struct XorgChangeWaiter {
+    damage: XorgDamage,
+    rectangle: Rectangle,
+}
+
+impl XorgChangeWaiter {
+    pub fn new(damage: XorgDamage, rectangle: Rectangle) -> XorgChangeWaiter {
+        XorgChangeWaiter { damage, rectangle }
+    }
+
+    fn overlaps(&self, rectangle: &Rectangle) -> bool {
+        let (a, b) = (&self.rectangle, rectangle);
+
+        (a.x as i32) < b.x as i32 + b.width as i32
+            && (b.x as i32) < a.x as i32 + a.width as i32
+            && (a.y as i32) < b.y as i32 + b.height as i32
+            && (b.y as i32) < a.y as i32 + a.height as i32
+    }
+}
+
+impl ChangeWaiter for XorgChangeWaiter {
+    fn wait(&mut self, timeout: Duration) -> XCapResult<()> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            let damaged_rectangles = self.damage.damaged_rectangles()?;
+            if damaged_rectangles
+                .iter()
+                .any(|rectangle| self.overlaps(rectangle))
+            {
+                return Ok(());
+            }
+
+            // 阻塞等待连接上的事件，不需要截图
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(());
+            }
+            self.damage.wait_for_event(deadline - now)?;
+        }
+    }
+}
@@ -1,26 +1,159 @@
 use super::impl_monitor::ImplMonitor;
-use crate::error::{XCapError, XCapResult};
-use crate::video_recorder::{Frame, RecorderWaker};
-use std::sync::mpsc::{self, Receiver, Sender};
-use std::sync::{Arc, Mutex};
+use super::utils::{get_current_screen_buf, get_monitor_info_buf};
+use super::xorg_capture::{xorg_capture, xorg_capture_native};
+use super::xorg_damage::XorgDamage;
+use crate::error::XCapResult;
+use crate::pixel_format::PixelFormat;
+use crate::video_recorder::{
+    DirtyRect, FormatTracker, Frame, FrameInterval, FrameSender, RecorderEvent, RecorderLifecycle,
+    RecorderOptions, RecorderRegion, RecorderState, RecorderWaker,
+};
+use std::sync::Arc;
+use std::sync::mpsc::Receiver;
 use std::thread;
 use std::time::Duration;
+use xcb::x::Rectangle;
+
+/// How long to wait for damage without a frame rate limit before checking the state again
+const DAMAGE_WAIT_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Damaged rectangles of the monitor, relative to the monitor
+fn get_monitor_damaged_rectangles(
+    damaged_rectangles: &[Rectangle],
+    monitor_rectangle: &Rectangle,
+) -> Vec<Rectangle> {
+    let monitor_left = monitor_rectangle.x as i32;
+    let monitor_top = monitor_rectangle.y as i32;
+    let monitor_right = monitor_left + monitor_rectangle.width as i32;
+    let monitor_bottom = monitor_top + monitor_rectangle.height as i32;
+
+    damaged_rectangles
+        .iter()
+        .filter_map(|rectangle| {
+            let left = (rectangle.x as i32).max(monitor_left);
+            let top = (rectangle.y as i32).max(monitor_top);
+            let right = (rectangle.x as i32 + rectangle.width as i32).min(monitor_right);
+            let bottom = (rectangle.y as i32 + rectangle.height as i32).min(monitor_bottom);
+
+            if right <= left || bottom <= top {
+                return None;
+            }
+
+            Some(Rectangle {
+                x: (left - monitor_left) as i16,
+                y: (top - monitor_top) as i16,
+                width: (right - left) as u16,
+                height: (bottom - top) as u16,
+            })
+        })
+        .collect()
+}
+
+/// 截图数据，每个像素 4 字节
+struct CapturedImage {
+    width: u32,
+    height: u32,
+    raw: Vec<u8>,
+    pixel_format: PixelFormat,
+}
+
+fn capture_rectangle(
+    rectangle: &Rectangle,
+    native_pixel_format: bool,
+) -> XCapResult<CapturedImage> {
+    let root = get_current_screen_buf()?.root();
+    let (x, y) = (rectangle.x as i32, rectangle.y as i32);
+    let (width, height) = (rectangle.width as u32, rectangle.height as u32);
+
+    let (raw, pixel_format) = if native_pixel_format {
+        xorg_capture_native(root, x, y, width, height)?
+    } else {
+        let rgba_image = xorg_capture(root, x, y, width, height, false)?;
+        (rgba_image.into_raw(), PixelFormat::Rgba)
+    };
+
+    Ok(CapturedImage {
+        width,
+        height,
+        raw,
+        pixel_format,
+    })
+}
+
+/// Copy only the damaged rectangles into the previous image, when they are few and small enough
+fn update_damaged_rectangles(
+    image: &mut CapturedImage,
+    damaged_rectangles: &[Rectangle],
+    monitor_rectangle: &Rectangle,
+    native_pixel_format: bool,
+) -> XCapResult<bool> {
+    let damaged_area: u32 = damaged_rectangles
+        .iter()
+        .map(|rectangle| rectangle.width as u32 * rectangle.height as u32)
+        .sum();
+
+    if image.width != monitor_rectangle.width as u32
+        || image.height != monitor_rectangle.height as u32
+        || damaged_rectangles.len() > 32
+        || damaged_area * 2 > image.width * image.height
+    {
+        return Ok(false);
+    }
+
+    for rectangle in damaged_rectangles {
+        let damaged_image = capture_rectangle(
+            &Rectangle {
+                x: monitor_rectangle.x + rectangle.x,
+                y: monitor_rectangle.y + rectangle.y,
+                width: rectangle.width,
+                height: rectangle.height,
+            },
+            native_pixel_format,
+        )?;
+
+        if damaged_image.pixel_format != image.pixel_format {
+            return Ok(false);
+        }
+
+        let row_size = damaged_image.width as usize * 4;
+        for (row, src) in damaged_image.raw.chunks_exact(row_size).enumerate() {
+            let offset =
+                ((rectangle.y as usize + row) * image.width as usize + rectangle.x as usize) * 4;
+            image.raw[offset..offset + row_size].copy_from_slice(src);
+        }
+    }
+
+    Ok(true)
+}
 
 #[derive(Debug, Clone)]
 pub struct XorgVideoRecorder {
     monitor: ImplMonitor,
-    sender: Sender<Frame>,
-    running: Arc<Mutex<bool>>,
+    region: Option<RecorderRegion>,
+    options: RecorderOptions,
+    sender: FrameSender,
+    lifecycle: RecorderLifecycle,
     recorder_waker: Arc<RecorderWaker>,
 }
 
 impl XorgVideoRecorder {
-    pub fn new(monitor: ImplMonitor) -> XCapResult<(Self, Receiver<Frame>)> {
-        let (sender, receiver) = mpsc::channel();
+    pub fn new(
+        monitor: ImplMonitor,
+        region: Option<RecorderRegion>,
+        options: RecorderOptions,
+    ) -> XCapResult<(Self, Receiver<Frame>)> {
+        let lifecycle = RecorderLifecycle::new();
+        let (sender, receiver) = FrameSender::new(
+            options.channel_capacity,
+            options.backpressure,
+            lifecycle.events(),
+        );
         let recorder = Self {
             monitor,
+            region,
+            options,
             sender,
-            running: Arc::new(Mutex::new(false)),
+            lifecycle,
             recorder_waker: Arc::new(RecorderWaker::new()),
         };
 
@@ -31,73 +164,209 @@ impl XorgVideoRecorder {
 
     pub fn on_frame(&self) -> XCapResult<()> {
         let monitor = self.monitor.clone();
+        let options = self.options.clone();
         let sender = self.sender.clone();
-        let running_flag = self.running.clone();
+        let lifecycle = self.lifecycle.clone();
         let recorder_waker = self.recorder_waker.clone();
+        let region = self.region;
+
+        self.lifecycle.spawn(move || {
+            // 不支持 XDamage 时，退化为连续截图
+            let xorg_damage = match XorgDamage::new() {
+                Ok(xorg_damage) => Some(xorg_damage),
+                Err(err) => {
+                    log::debug!("XorgDamage::new failed {err}");
+                    None
+                }
+            };
+
+            let events = lifecycle.events();
+            let mut last_image: Option<CapturedImage> = None;
+            let mut format_tracker = FormatTracker::default();
+            let mut sequence = 0;
+            let mut frame_interval = FrameInterval::from_options(&options);
 
-        thread::spawn(move || {
             loop {
                 if let Err(err) = recorder_waker.wait() {
                     log::error!("Recorder waker error: {err:?}");
                     break Err(err);
                 }
 
-                let is_running = match running_flag.lock() {
-                    Ok(guard) => *guard,
+                match lifecycle.state() {
+                    Ok(RecorderState::Running) => {}
+                    Ok(RecorderState::Closed) => break Ok(()),
+                    Ok(_) => {
+                        // 暂停时等待状态变化，不再轮询
+                        match lifecycle.wait_while_paused() {
+                            Ok(RecorderState::Running) => {}
+                            Ok(_) => break Ok(()),
+                            Err(e) => break Err(e),
+                        }
+                        continue;
+                    }
+                    Err(e) => break Err(e),
+                }
+
+                frame_interval.wait();
+
+                let record_rectangle = match get_monitor_info_buf(monitor.output) {
+                    // 只截取需要录制的区域
+                    Ok(monitor_info_buf) => match region {
+                        Some(region) => Rectangle {
+                            x: monitor_info_buf.x() + region.rect.x as i16,
+                            y: monitor_info_buf.y() + region.rect.y as i16,
+                            width: region.rect.width as u16,
+                            height: region.rect.height as u16,
+                        },
+                        None => Rectangle {
+                            x: monitor_info_buf.x(),
+                            y: monitor_info_buf.y(),
+                            width: monitor_info_buf.width(),
+                            height: monitor_info_buf.height(),
+                        },
+                    },
                     Err(e) => {
-                        log::error!("Failed to lock running flag: {e:?}");
-                        break Err(XCapError::from(e));
+                        log::error!("Failed to get monitor info: {e:?}");
+                        events.error(e);
+                        thread::sleep(Duration::from_millis(10));
+                        continue;
                     }
                 };
 
-                if !is_running {
-                    break Ok(());
-                }
+                let mut dirty_rects = Vec::new();
+
+                let image = match (&xorg_damage, last_image.take()) {
+                    (Some(xorg_damage), Some(mut image)) => {
+                        let damaged_rectangles = match xorg_damage.damaged_rectangles() {
+                            Ok(damaged_rectangles) => damaged_rectangles,
+                            Err(e) => {
+                                log::error!("Failed to get damaged rectangles: {e:?}");
+                                events.error(e);
+                                thread::sleep(Duration::from_millis(10));
+                                continue;
+                            }
+                        };
+
+                        let damaged_rectangles =
+                            get_monitor_damaged_rectangles(&damaged_rectangles, &record_rectangle);
+
+                        // 没有变化则不发送帧，等待新的 damage 事件直到下一帧
+                        if damaged_rectangles.is_empty() {
+                            last_image = Some(image);
+                            let timeout = frame_interval.remaining().unwrap_or(DAMAGE_WAIT_TIMEOUT);
+                            if let Err(e) = xorg_damage.wait_for_event(timeout) {
+                                log::error!("Failed to wait for damage: {e:?}");
+                                events.error(e);
+                                thread::sleep(Duration::from_millis(10));
+                            }
+                            continue;
+                        }
+
+                        dirty_rects = damaged_rectangles
+                            .iter()
+                            .map(|rectangle| DirtyRect {
+                                x: rectangle.x as u32,
+                                y: rectangle.y as u32,
+                                width: rectangle.width as u32,
+                                height: rectangle.height as u32,
+                            })
+                            .collect();
 
-                match monitor.capture_image() {
+                        match update_damaged_rectangles(
+                            &mut image,
+                            &damaged_rectangles,
+                            &record_rectangle,
+                            options.native_pixel_format,
+                        ) {
+                            Ok(true) => Ok(image),
+                            Ok(false) => {
+                                capture_rectangle(&record_rectangle, options.native_pixel_format)
+                            }
+                            Err(e) => Err(e),
+                        }
+                    }
+                    _ => capture_rectangle(&record_rectangle, options.native_pixel_format),
+                };
+
+                match image {
                     Ok(image) => {
-                        let width = image.width();
-                        let height = image.height();
-                        let raw = image.into_raw();
-
-                        let frame = Frame::new(width, height, raw);
-                        if let Err(e) = sender.send(frame) {
-                            log::error!("Failed to send frame: {e:?}");
-                            break Err(XCapError::new(format!("Failed to send frame: {e}")));
+                        let frame = Frame::new(image.width, image.height, image.raw.clone())
+                            .with_pixel_format(image.pixel_format)
+                            .with_dirty_rects(dirty_rects)
+                            .with_sequence(sequence);
+
+                        match options.scale_frame(frame) {
+                            Ok(frame) => {
+                                // damage 驱动时帧率不固定
+                                let fps = match xorg_damage {
+                                    Some(_) => None,
+                                    None => options.target_fps,
+                                };
+                                format_tracker.update(&frame, fps, events);
+                                if let Err(e) = sender.send(frame) {
+                                    log::error!("Failed to send frame: {e:?}");
+                                    break Err(e);
+                                }
+                                sequence += 1;
+
+                                // 帧没有送出时不保留，下一帧重新完整截图，避免丢失变化区域
+                                if xorg_damage.is_some() {
+                                    last_image = Some(image);
+                                }
+                            }
+                            Err(e) => {
+                                log::error!("Failed to scale frame: {e:?}");
+                                events.error(e);
+                            }
                         }
                     }
                     Err(e) => {
                         log::error!("Failed to capture frame: {e:?}");
+                        events.error(e);
                         thread::sleep(Duration::from_millis(10));
                         continue;
                     }
                 }
 
-                thread::sleep(Duration::from_millis(1));
+                if xorg_damage.is_none() {
+                    thread::sleep(Duration::from_millis(1));
+                }
             }
-        });
-
-        Ok(())
+        })
     }
 
     pub fn start(&self) -> XCapResult<()> {
-        let mut running = self.running.lock().map_err(XCapError::from)?;
-        if *running {
-            return Ok(());
+        if self.lifecycle.start()? {
+            self.recorder_waker.wake()?;
         }
-        *running = true;
-
-        self.recorder_waker.wake()?;
 
         Ok(())
     }
 
     pub fn stop(&self) -> XCapResult<()> {
-        let mut running = self.running.lock().map_err(XCapError::from)?;
-        *running = false;
-
+        self.lifecycle.stop()?;
         self.recorder_waker.sleep()?;
 
         Ok(())
     }
+
+    pub fn close(&self) -> XCapResult<()> {
+        if !self.lifecycle.close()? {
+            return Ok(());
+        }
+
+        self.sender.close();
+        // 唤醒暂停中的线程，让它看到关闭状态后退出
+        self.recorder_waker.wake()?;
+
+        self.lifecycle.join()
+    }
+
+    pub fn state(&self) -> XCapResult<RecorderState> {
+        self.lifecycle.state()
+    }
+
+    pub fn events(&self) -> XCapResult<Receiver<RecorderEvent>> {
+        self.lifecycle.events().subscribe()
+    }
 }
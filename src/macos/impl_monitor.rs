@@ -12,8 +12,9 @@ use objc2_core_graphics::{
 use objc2_foundation::{NSNumber, NSString};
 
 use crate::{
+    change_waiter::{ChangeWaiter, PollingWaiter},
     error::{XCapError, XCapResult},
-    video_recorder::Frame,
+    video_recorder::{DirtyRect, Frame, RecorderOptions, RecorderRegion},
 };
 
 use super::{capture::capture, impl_video_recorder::ImplVideoRecorder};
@@ -202,7 +203,7 @@ impl ImplMonitor {
         capture(cg_rect, CGWindowListOption::OptionAll, 0)
     }
 
-    pub fn capture_region(&self, x: u32, y: u32, width: u32, height: u32) -> XCapResult<RgbaImage> {
+    fn check_region(&self, x: u32, y: u32, width: u32, height: u32) -> XCapResult<()> {
         // Validate region bounds
         let monitor_x = self.x()?;
         let monitor_y = self.y()?;
@@ -220,11 +221,17 @@ impl ImplMonitor {
             )));
         }
 
+        Ok(())
+    }
+
+    pub fn capture_region(&self, x: u32, y: u32, width: u32, height: u32) -> XCapResult<RgbaImage> {
+        self.check_region(x, y, width, height)?;
+
         // Create a CGRect for the region to capture
         let cg_rect = objc2_core_foundation::CGRect {
             origin: objc2_core_foundation::CGPoint {
-                x: (monitor_x + x as i32) as f64,
-                y: (monitor_y + y as i32) as f64,
+                x: (self.x()? + x as i32) as f64,
+                y: (self.y()? + y as i32) as f64,
             },
             size: objc2_core_foundation::CGSize {
                 width: width as f64,
@@ -235,7 +242,40 @@ impl ImplMonitor {
         capture(cg_rect, CGWindowListOption::OptionAll, 0)
     }
 
-    pub fn video_recorder(&self) -> XCapResult<(ImplVideoRecorder, Receiver<Frame>)> {
-        ImplVideoRecorder::new(self.cg_direct_display_id)
+    pub fn change_waiter(&self, region: &DirtyRect) -> XCapResult<Box<dyn ChangeWaiter>> {
+        self.check_region(region.x, region.y, region.width, region.height)?;
+
+        Ok(Box::new(PollingWaiter::default()))
+    }
+
+    pub fn video_recorder(
+        &self,
+        options: &RecorderOptions,
+    ) -> XCapResult<(ImplVideoRecorder, Receiver<Frame>)> {
+        ImplVideoRecorder::new(self.cg_direct_display_id, None, options)
+    }
+
+    pub fn region_video_recorder(
+        &self,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        options: &RecorderOptions,
+    ) -> XCapResult<(ImplVideoRecorder, Receiver<Frame>)> {
+        self.check_region(x, y, width, height)?;
+
+        let region = RecorderRegion {
+            rect: DirtyRect {
+                x,
+                y,
+                width,
+                height,
+            },
+            monitor_width: self.width()?,
+            monitor_height: self.height()?,
+        };
+
+        ImplVideoRecorder::new(self.cg_direct_display_id, Some(region), options)
     }
 }
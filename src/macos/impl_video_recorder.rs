@@ -1,6 +1,10 @@
 use std::{
     slice,
-    sync::mpsc::{Receiver, SyncSender, sync_channel},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+        mpsc::Receiver,
+    },
 };
 
 use dispatch2::{DispatchQueue, DispatchQueueAttr};
@@ -22,11 +26,27 @@ use objc2_core_video::{
 use objc2_foundation::{NSDictionary, NSNumber, NSObject, NSObjectProtocol, NSString};
 use scopeguard::defer;
 
-use crate::{XCapError, XCapResult, video_recorder::Frame};
+use crate::{
+    XCapError, XCapResult,
+    pixel_format::PixelFormat,
+    video_recorder::{
+        FormatTracker, Frame, FrameInterval, FrameSender, RecorderEvent, RecorderEvents,
+        RecorderLifecycle, RecorderOptions, RecorderRegion, RecorderState,
+    },
+    window_video_recorder::WindowVideoRecorder,
+};
+
+use super::impl_window::ImplWindow;
 
 #[derive(Debug, Clone)]
 struct DataOutputSampleBufferDelegateVars {
-    tx: SyncSender<Frame>,
+    tx: FrameSender,
+    sequence: Arc<AtomicU64>,
+    region: Option<RecorderRegion>,
+    options: RecorderOptions,
+    frame_interval: Arc<Mutex<FrameInterval>>,
+    format_tracker: Arc<Mutex<FormatTracker>>,
+    events: RecorderEvents,
 }
 
 impl DataOutputSampleBufferDelegateVars {
@@ -47,10 +67,25 @@ impl DataOutputSampleBufferDelegateVars {
                 CVPixelBufferUnlockBaseAddress(&pixel_buffer, CVPixelBufferLockFlags::ReadOnly);
             };
 
+            match self.frame_interval.lock() {
+                Ok(mut frame_interval) if !frame_interval.ready() => {
+                    self.events.emit(RecorderEvent::FramesDropped(1));
+                    return;
+                }
+                Err(err) => {
+                    log::error!("Failed to lock frame interval: {err:?}");
+                    return;
+                }
+                _ => {}
+            }
+
             let format_type = CVPixelBufferGetPixelFormatType(&pixel_buffer);
 
             if format_type != kCVPixelFormatType_32BGRA {
                 log::error!("pixel format type {format_type} is not supported");
+                self.events.error(XCapError::new(format!(
+                    "pixel format type {format_type} is not supported"
+                )));
                 return;
             }
 
@@ -66,15 +101,49 @@ impl DataOutputSampleBufferDelegateVars {
                 buffer.extend_from_slice(&row[..width * 4]);
             }
 
-            for bgra in buffer.chunks_exact_mut(4) {
-                bgra.swap(0, 2);
+            let pixel_format = if self.options.native_pixel_format {
+                PixelFormat::Bgra
+            } else {
+                for bgra in buffer.chunks_exact_mut(4) {
+                    bgra.swap(0, 2);
+                }
+
+                PixelFormat::Rgba
+            };
+
+            let sequence = self.sequence.load(Ordering::Relaxed);
+            let frame = Frame::new(width as u32, height as u32, buffer)
+                .with_pixel_format(pixel_format)
+                .with_sequence(sequence);
+
+            // 只录制区域时，先裁剪再缩放
+            let frame = match self.region.map(|region| region.crop(&frame)) {
+                None => frame,
+                Some(Ok(Some(frame))) => frame,
+                Some(Ok(None)) => return,
+                Some(Err(err)) => {
+                    log::error!("Failed to crop frame: {err:?}");
+                    self.events.error(err);
+                    return;
+                }
+            };
+
+            let frame = match self.options.scale_frame(frame) {
+                Ok(frame) => frame,
+                Err(err) => {
+                    log::error!("Failed to scale frame: {err:?}");
+                    self.events.error(err);
+                    return;
+                }
+            };
+
+            if let Ok(mut format_tracker) = self.format_tracker.lock() {
+                format_tracker.update(&frame, self.options.target_fps, &self.events);
             }
 
-            let _ = self.tx.send(Frame {
-                width: width as u32,
-                height: height as u32,
-                raw: buffer,
-            });
+            if self.tx.send(frame).is_ok() {
+                self.sequence.store(sequence + 1, Ordering::Relaxed);
+            }
         }
     }
 }
@@ -102,22 +171,41 @@ define_class!(
 unsafe impl NSObjectProtocol for DataOutputSampleBufferDelegate {}
 
 impl DataOutputSampleBufferDelegate {
-    fn new(tx: SyncSender<Frame>) -> Retained<Self> {
-        let this = Self::alloc().set_ivars(DataOutputSampleBufferDelegateVars { tx });
+    fn new(
+        tx: FrameSender,
+        region: Option<RecorderRegion>,
+        options: &RecorderOptions,
+        events: &RecorderEvents,
+    ) -> Retained<Self> {
+        let this = Self::alloc().set_ivars(DataOutputSampleBufferDelegateVars {
+            tx,
+            sequence: Arc::new(AtomicU64::new(0)),
+            region,
+            options: options.clone(),
+            frame_interval: Arc::new(Mutex::new(FrameInterval::from_options(options))),
+            format_tracker: Arc::new(Mutex::new(FormatTracker::default())),
+            events: events.clone(),
+        });
         unsafe { msg_send![super(this), init] }
     }
 }
 
 #[derive(Debug, Clone)]
-pub struct ImplVideoRecorder {
+pub struct MonitorVideoRecorder {
     session: Retained<AVCaptureSession>,
     _input: Retained<AVCaptureScreenInput>,
     _output: Retained<AVCaptureVideoDataOutput>,
     _delegate: Retained<DataOutputSampleBufferDelegate>,
+    tx: FrameSender,
+    lifecycle: RecorderLifecycle,
 }
 
-impl ImplVideoRecorder {
-    pub fn new(cg_direct_display_id: CGDirectDisplayID) -> XCapResult<(Self, Receiver<Frame>)> {
+impl MonitorVideoRecorder {
+    pub fn new(
+        cg_direct_display_id: CGDirectDisplayID,
+        region: Option<RecorderRegion>,
+        options: &RecorderOptions,
+    ) -> XCapResult<(Self, Receiver<Frame>)> {
         unsafe {
             let session = AVCaptureSession::new();
             let input = AVCaptureScreenInput::initWithDisplayID(
@@ -162,9 +250,19 @@ impl ImplVideoRecorder {
                 session.addOutput(&output)
             }
 
-            let (tx, rx) = sync_channel(0);
+            let lifecycle = RecorderLifecycle::new();
+            let (tx, rx) = FrameSender::new(
+                options.channel_capacity.or(Some(0)),
+                options.backpressure,
+                lifecycle.events(),
+            );
 
-            let delegate = DataOutputSampleBufferDelegate::new(tx.clone());
+            let delegate = DataOutputSampleBufferDelegate::new(
+                tx.clone(),
+                region,
+                options,
+                lifecycle.events(),
+            );
 
             let sample_buffer_delegate = ProtocolObject::<
                 dyn AVCaptureVideoDataOutputSampleBufferDelegate,
@@ -179,11 +277,13 @@ impl ImplVideoRecorder {
                 msg_send![&output, setSampleBufferDelegate: sample_buffer_delegate, queue: queue];
 
             Ok((
-                ImplVideoRecorder {
+                MonitorVideoRecorder {
                     session,
                     _output: output,
                     _input: input,
                     _delegate: delegate,
+                    tx,
+                    lifecycle,
                 },
                 rx,
             ))
@@ -191,12 +291,95 @@ impl ImplVideoRecorder {
     }
 
     pub fn start(&self) -> XCapResult<()> {
-        unsafe { self.session.startRunning() };
+        if self.lifecycle.start()? {
+            unsafe { self.session.startRunning() };
+        }
         Ok(())
     }
 
     pub fn stop(&self) -> XCapResult<()> {
+        self.lifecycle.stop()?;
+        unsafe { self.session.stopRunning() };
+        Ok(())
+    }
+
+    pub fn close(&self) -> XCapResult<()> {
+        if !self.lifecycle.close()? {
+            return Ok(());
+        }
+
+        // stopRunning 会等待正在执行的回调结束
+        self.tx.close();
         unsafe { self.session.stopRunning() };
         Ok(())
     }
+
+    pub fn state(&self) -> XCapResult<RecorderState> {
+        self.lifecycle.state()
+    }
+
+    pub fn events(&self) -> XCapResult<Receiver<RecorderEvent>> {
+        self.lifecycle.events().subscribe()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ImplVideoRecorder {
+    Monitor(MonitorVideoRecorder),
+    Window(WindowVideoRecorder),
+}
+
+impl ImplVideoRecorder {
+    pub fn new(
+        cg_direct_display_id: CGDirectDisplayID,
+        region: Option<RecorderRegion>,
+        options: &RecorderOptions,
+    ) -> XCapResult<(Self, Receiver<Frame>)> {
+        let (recorder, receiver) =
+            MonitorVideoRecorder::new(cg_direct_display_id, region, options)?;
+        Ok((ImplVideoRecorder::Monitor(recorder), receiver))
+    }
+
+    pub fn new_window(
+        window: ImplWindow,
+        options: &RecorderOptions,
+    ) -> XCapResult<(Self, Receiver<Frame>)> {
+        let (recorder, receiver) = WindowVideoRecorder::new(window, options.clone())?;
+        Ok((ImplVideoRecorder::Window(recorder), receiver))
+    }
+
+    pub fn start(&self) -> XCapResult<()> {
+        match self {
+            ImplVideoRecorder::Monitor(recorder) => recorder.start(),
+            ImplVideoRecorder::Window(recorder) => recorder.start(),
+        }
+    }
+
+    pub fn stop(&self) -> XCapResult<()> {
+        match self {
+            ImplVideoRecorder::Monitor(recorder) => recorder.stop(),
+            ImplVideoRecorder::Window(recorder) => recorder.stop(),
+        }
+    }
+
+    pub fn close(&self) -> XCapResult<()> {
+        match self {
+            ImplVideoRecorder::Monitor(recorder) => recorder.close(),
+            ImplVideoRecorder::Window(recorder) => recorder.close(),
+        }
+    }
+
+    pub fn state(&self) -> XCapResult<RecorderState> {
+        match self {
+            ImplVideoRecorder::Monitor(recorder) => recorder.state(),
+            ImplVideoRecorder::Window(recorder) => recorder.state(),
+        }
+    }
+
+    pub fn events(&self) -> XCapResult<Receiver<RecorderEvent>> {
+        match self {
+            ImplVideoRecorder::Monitor(recorder) => recorder.events(),
+            ImplVideoRecorder::Window(recorder) => recorder.events(),
+        }
+    }
 }
@@ -1,4 +1,4 @@
-use std::ffi::c_void;
+use std::{ffi::c_void, sync::mpsc::Receiver};
 
 use image::RgbaImage;
 use objc2_app_kit::NSWorkspace;
@@ -11,9 +11,14 @@ use objc2_core_graphics::{
 };
 use objc2_foundation::{NSNumber, NSString};
 
-use crate::{XCapError, error::XCapResult};
+use crate::{
+    XCapError,
+    error::XCapResult,
+    video_recorder::{Frame, RecorderOptions},
+    window::{FrameExtents, WindowCaptureOptions},
+};
 
-use super::{capture::capture, impl_monitor::ImplMonitor};
+use super::{capture::capture, impl_monitor::ImplMonitor, impl_video_recorder::ImplVideoRecorder};
 
 #[derive(Debug, Clone)]
 pub(crate) struct ImplWindow {
@@ -377,4 +382,24 @@ impl ImplWindow {
             self.window_id,
         )
     }
+
+    pub fn frame_extents(&self) -> XCapResult<FrameExtents> {
+        // 窗口边界已包含标题栏
+        Ok(FrameExtents::default())
+    }
+
+    /// The options only apply on X11
+    pub fn capture_image_with_options(
+        &self,
+        _options: &WindowCaptureOptions,
+    ) -> XCapResult<RgbaImage> {
+        self.capture_image()
+    }
+
+    pub fn video_recorder(
+        &self,
+        options: &RecorderOptions,
+    ) -> XCapResult<(ImplVideoRecorder, Receiver<Frame>)> {
+        ImplVideoRecorder::new_window(self.clone(), options)
+    }
 }
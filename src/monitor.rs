@@ -1,9 +1,13 @@
-use std::sync::mpsc::Receiver;
+use std::{sync::mpsc::Receiver, time::Duration};
 
 use image::RgbaImage;
 
 use crate::{
-    VideoRecorder, error::XCapResult, platform::impl_monitor::ImplMonitor, video_recorder::Frame,
+    VideoRecorder,
+    change_waiter::ChangeWaiter,
+    error::{XCapError, XCapResult},
+    platform::impl_monitor::ImplMonitor,
+    video_recorder::{DirtyRect, Frame, RecorderOptions},
 };
 
 #[derive(Debug, Clone)]
@@ -93,18 +97,200 @@ impl Monitor {
     }
 
     pub fn video_recorder(&self) -> XCapResult<(VideoRecorder, Receiver<Frame>)> {
-        let (impl_video_recorder, sx) = self.impl_monitor.video_recorder()?;
+        self.video_recorder_with_options(&RecorderOptions::default())
+    }
+
+    pub fn video_recorder_with_options(
+        &self,
+        options: &RecorderOptions,
+    ) -> XCapResult<(VideoRecorder, Receiver<Frame>)> {
+        options.validate()?;
+
+        let (impl_video_recorder, sx) = self.impl_monitor.video_recorder(options)?;
+
+        Ok((VideoRecorder::new(impl_video_recorder), sx))
+    }
+
+    /// Record a region of the monitor, validated like [`Monitor::capture_region`].
+    pub fn region_video_recorder(
+        &self,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) -> XCapResult<(VideoRecorder, Receiver<Frame>)> {
+        self.region_video_recorder_with_options(x, y, width, height, &RecorderOptions::default())
+    }
+
+    pub fn region_video_recorder_with_options(
+        &self,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        options: &RecorderOptions,
+    ) -> XCapResult<(VideoRecorder, Receiver<Frame>)> {
+        options.validate()?;
+
+        let (impl_video_recorder, sx) = self
+            .impl_monitor
+            .region_video_recorder(x, y, width, height, options)?;
 
         Ok((VideoRecorder::new(impl_video_recorder), sx))
     }
 }
 
+impl Monitor {
+    /// The rectangle to watch, the whole monitor when `region` is `None`
+    fn watch_rect(&self, region: Option<DirtyRect>) -> XCapResult<DirtyRect> {
+        match region {
+            Some(region) => Ok(region),
+            None => Ok(DirtyRect {
+                x: 0,
+                y: 0,
+                width: self.width()?,
+                height: self.height()?,
+            }),
+        }
+    }
+
+    /// Wait until a region of the monitor, the whole monitor when `None`, looks different
+    /// from when the call started, and return the new image.
+    ///
+    /// Uses XDamage events on X11 and polls elsewhere, every 50 ms. On Wayland each poll is a
+    /// full screenshot through the portal or screencopy, so it only polls every 500 ms.
+    /// Returns [`XCapError::Timeout`] if nothing changed within `timeout`.
+    pub fn wait_for_change(
+        &self,
+        region: Option<DirtyRect>,
+        timeout: Duration,
+    ) -> XCapResult<RgbaImage> {
+        let rect = self.watch_rect(region)?;
+        let mut waiter = self.impl_monitor.change_waiter(&rect)?;
+
+        wait_for_change(
+            || self.capture_region(rect.x, rect.y, rect.width, rect.height),
+            waiter.as_mut(),
+            timeout,
+        )
+    }
+
+    /// Wait until a region of the monitor, the whole monitor when `None`, stays the same
+    /// for `quiet_period`, and return the final image.
+    ///
+    /// Returns [`XCapError::Timeout`] if the region still changes after `timeout`.
+    pub fn wait_until_stable(
+        &self,
+        region: Option<DirtyRect>,
+        quiet_period: Duration,
+        timeout: Duration,
+    ) -> XCapResult<RgbaImage> {
+        let rect = self.watch_rect(region)?;
+        let mut waiter = self.impl_monitor.change_waiter(&rect)?;
+
+        wait_until_stable(
+            || self.capture_region(rect.x, rect.y, rect.width, rect.height),
+            waiter.as_mut(),
+            quiet_period,
+            timeout,
+        )
+    }
+}
+
+fn wait_for_change<F>(
+    mut capture: F,
+    waiter: &mut dyn ChangeWaiter,
+    timeout: Duration,
+) -> XCapResult<RgbaImage>
+where
+    F: FnMut() -> XCapResult<RgbaImage>,
+{
+    let deadline = waiter.now() + timeout;
+    let initial = capture()?;
+
+    loop {
+        let now = waiter.now();
+        if now >= deadline {
+            return Err(XCapError::Timeout(format!(
+                "Region did not change within {timeout:?}"
+            )));
+        }
+
+        waiter.wait(deadline - now)?;
+
+        let image = capture()?;
+        if image != initial {
+            return Ok(image);
+        }
+    }
+}
+
+fn wait_until_stable<F>(
+    mut capture: F,
+    waiter: &mut dyn ChangeWaiter,
+    quiet_period: Duration,
+    timeout: Duration,
+) -> XCapResult<RgbaImage>
+where
+    F: FnMut() -> XCapResult<RgbaImage>,
+{
+    let deadline = waiter.now() + timeout;
+    let mut image = capture()?;
+    let mut changed_at = waiter.now();
+
+    loop {
+        let now = waiter.now();
+        let quiet_until = changed_at + quiet_period;
+        if now >= quiet_until {
+            return Ok(image);
+        }
+        if now >= deadline {
+            return Err(XCapError::Timeout(format!(
+                "Region did not stay unchanged for {quiet_period:?} within {timeout:?}"
+            )));
+        }
+
+        // 等到安静期结束或超时，期间有变化时提前醒来
+        waiter.wait(quiet_until.min(deadline) - now)?;
+
+        let next = capture()?;
+        if next != image {
+            image = next;
+            changed_at = waiter.now();
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::XCapError;
+    use std::time::Instant;
 
     use super::*;
 
+    /// 使用虚拟时钟，每次等待推进 50 ms，不真正睡眠
+    struct FakeWaiter {
+        now: Instant,
+    }
+
+    impl FakeWaiter {
+        fn new() -> FakeWaiter {
+            FakeWaiter {
+                now: Instant::now(),
+            }
+        }
+    }
+
+    impl ChangeWaiter for FakeWaiter {
+        fn wait(&mut self, timeout: Duration) -> XCapResult<()> {
+            self.now += timeout.min(Duration::from_millis(50));
+            Ok(())
+        }
+
+        fn now(&self) -> Instant {
+            self.now
+        }
+    }
+
     #[test]
     fn test_capture_region_out_of_bounds() {
         let monitors = Monitor::all().unwrap();
@@ -123,4 +309,43 @@ mod tests {
             _ => panic!("Expected InvalidCaptureRegion error"),
         }
     }
+
+    #[test]
+    fn test_wait_for_change_and_stable() {
+        // 前三次截图变化，之后保持不变
+        let mut count = 0u8;
+        let capture = |count: &mut u8| {
+            *count += 1;
+            Ok(RgbaImage::from_pixel(
+                2,
+                2,
+                image::Rgba([(*count).min(3), 0, 0, 255]),
+            ))
+        };
+
+        let image = wait_for_change(
+            || capture(&mut count),
+            &mut FakeWaiter::new(),
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        assert_eq!(image.get_pixel(0, 0)[0], 2);
+
+        let image = wait_until_stable(
+            || capture(&mut count),
+            &mut FakeWaiter::new(),
+            Duration::from_millis(120),
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        assert_eq!(image.get_pixel(0, 0)[0], 3);
+
+        let err = wait_for_change(
+            || capture(&mut count),
+            &mut FakeWaiter::new(),
+            Duration::from_millis(120),
+        )
+        .unwrap_err();
+        assert!(matches!(err, XCapError::Timeout(_)));
+    }
 }
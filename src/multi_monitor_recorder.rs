@@ -0,0 +1,532 @@
+use std::{
+    sync::{
+        Arc,
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    Monitor, VideoRecorder, XCapError, XCapResult,
+    pixel_format::PixelFormat,
+    video_recorder::{
+        BackpressurePolicy, DirtyRect, FormatTracker, Frame, FrameInterval, FrameSender,
+        RecorderEvent, RecorderEvents, RecorderLifecycle, RecorderOptions, RecorderState,
+    },
+};
+
+/// How long the canvas waits for every monitor's first frame before emitting without it
+const SOURCE_WAIT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Where a monitor's frames go on the canvas
+#[derive(Debug, Clone, Copy)]
+struct SourceLayout {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+/// The canvas covering every monitor, in monitor coordinates
+#[derive(Debug)]
+struct Canvas {
+    width: u32,
+    height: u32,
+    raw: Vec<u8>,
+    dirty_rects: Vec<DirtyRect>,
+    /// The newest frame drawn since the last emitted frame
+    latest: Option<Frame>,
+    /// The timestamp of the last frame drawn from each source
+    source_timestamps: Vec<Option<Duration>>,
+    /// When the first frame of any source was drawn
+    first_drawn_at: Option<Instant>,
+    /// The timestamp of the last emitted frame
+    last_timestamp: Option<Duration>,
+}
+
+impl Canvas {
+    fn new(width: u32, height: u32, sources: usize) -> Canvas {
+        Canvas {
+            width,
+            height,
+            raw: vec![0; width as usize * height as usize * 4],
+            dirty_rects: Vec::new(),
+            latest: None,
+            source_timestamps: vec![None; sources],
+            first_drawn_at: None,
+            last_timestamp: None,
+        }
+    }
+
+    fn draw(&mut self, index: usize, layout: &SourceLayout, frame: Frame) -> XCapResult<()> {
+        // 丢弃比该显示器上一帧更旧的帧，避免画面回退
+        if self.source_timestamps[index].is_some_and(|timestamp| frame.timestamp < timestamp) {
+            return Ok(());
+        }
+
+        // 不同缩放比例的显示器统一缩放到显示器坐标
+        let frame = if frame.width != layout.width || frame.height != layout.height {
+            frame.resize(layout.width, layout.height)?
+        } else {
+            frame
+        };
+        let frame = if frame.pixel_format != PixelFormat::Rgba {
+            frame.convert(PixelFormat::Rgba)?
+        } else {
+            frame
+        };
+
+        let row_size = layout.width as usize * 4;
+        for y in 0..layout.height {
+            let row = frame
+                .row(y)
+                .ok_or_else(|| XCapError::new("Frame data is too short"))?;
+            let offset = ((layout.y + y) as usize * self.width as usize + layout.x as usize) * 4;
+            self.raw[offset..offset + row_size].copy_from_slice(&row[..row_size]);
+        }
+
+        // 和 Frame::merge_dirty_rects 一样，变化区域太多时认为整个画布都变化了
+        let whole_canvas = DirtyRect {
+            x: 0,
+            y: 0,
+            width: self.width,
+            height: self.height,
+        };
+        if self.dirty_rects != [whole_canvas] {
+            self.dirty_rects
+                .extend(frame.dirty_rects.iter().map(|dirty_rect| DirtyRect {
+                    x: layout.x + dirty_rect.x,
+                    y: layout.y + dirty_rect.y,
+                    ..*dirty_rect
+                }));
+            if self.dirty_rects.len() > 32 {
+                self.dirty_rects = vec![whole_canvas];
+            }
+        }
+
+        self.source_timestamps[index] = Some(frame.timestamp);
+        self.first_drawn_at.get_or_insert_with(Instant::now);
+
+        if self
+            .latest
+            .as_ref()
+            .is_none_or(|latest| frame.timestamp > latest.timestamp)
+        {
+            self.latest = Some(frame);
+        }
+
+        Ok(())
+    }
+
+    /// Whether a frame can be emitted: something was drawn since the last one, and every
+    /// source has delivered a frame or [`SOURCE_WAIT_TIMEOUT`] has passed.
+    fn is_ready(&self) -> bool {
+        if self.latest.is_none() {
+            return false;
+        }
+
+        self.source_timestamps.iter().all(Option::is_some)
+            || self
+                .first_drawn_at
+                .is_some_and(|first_drawn_at| first_drawn_at.elapsed() >= SOURCE_WAIT_TIMEOUT)
+    }
+
+    /// A frame of the canvas, `None` when nothing was drawn since the last one
+    fn take_frame(&mut self, sequence: u64) -> Option<Frame> {
+        let latest = self.latest.take()?;
+
+        // 晚到的旧帧不能让时间戳倒退
+        let timestamp = self
+            .last_timestamp
+            .map_or(latest.timestamp, |last| last.max(latest.timestamp));
+        self.last_timestamp = Some(timestamp);
+
+        let mut frame = Frame::new(self.width, self.height, self.raw.clone())
+            .with_dirty_rects(self.dirty_rects.drain(..).collect())
+            .with_sequence(sequence);
+        frame.timestamp = timestamp;
+        frame.system_time = latest.system_time;
+
+        Some(frame)
+    }
+}
+
+/// Forward a source's frames and events to the compositing thread
+fn forward_source(
+    index: usize,
+    frames: Receiver<Frame>,
+    source_events: Receiver<RecorderEvent>,
+    sender: Sender<(usize, Frame)>,
+    events: RecorderEvents,
+) {
+    thread::spawn(move || {
+        loop {
+            let frame = frames.recv_timeout(Duration::from_millis(50));
+
+            for event in source_events.try_iter() {
+                // 状态和格式由组合后的录制器自己发送
+                if matches!(
+                    event,
+                    RecorderEvent::Error(_)
+                        | RecorderEvent::SourceClosed
+                        | RecorderEvent::FramesDropped(_)
+                ) {
+                    events.emit(event);
+                }
+            }
+
+            match frame {
+                Ok(frame) => {
+                    if sender.send((index, frame)).is_err() {
+                        break;
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+    });
+}
+
+#[derive(Debug)]
+struct MultiMonitorRecorderInner {
+    sources: Vec<VideoRecorder>,
+    sender: FrameSender,
+    lifecycle: RecorderLifecycle,
+}
+
+impl MultiMonitorRecorderInner {
+    fn close(&self) -> XCapResult<()> {
+        if !self.lifecycle.close()? {
+            return Ok(());
+        }
+
+        self.sender.close();
+        // 关闭所有显示器的录制后，合成线程收不到新帧会退出
+        for source in &self.sources {
+            source.close()?;
+        }
+
+        self.lifecycle.join()
+    }
+}
+
+impl Drop for MultiMonitorRecorderInner {
+    fn drop(&mut self) {
+        if let Err(err) = self.close() {
+            log::error!("Failed to close recorder: {err:?}");
+        }
+    }
+}
+
+/// Records several monitors into one stream, laid out by monitor position.
+///
+/// Each frame combines the latest frame of every monitor and carries the timestamp of the
+/// newest one, never going backwards. A monitor's frames older than the one already drawn
+/// are dropped. The first frame waits up to a second for every monitor, the ones that have
+/// not delivered a frame by then stay black until they do.
+///
+/// Monitors are placed in monitor coordinates, frames of scaled monitors are resized to fit.
+/// On Wayland the user picks every monitor in a separate dialog, picking another monitor than
+/// the one asked for fails to create the recorder.
+#[derive(Debug, Clone)]
+pub struct MultiMonitorRecorder {
+    inner: Arc<MultiMonitorRecorderInner>,
+}
+
+impl MultiMonitorRecorder {
+    /// Record all monitors.
+    pub fn all() -> XCapResult<(MultiMonitorRecorder, Receiver<Frame>)> {
+        MultiMonitorRecorder::new(&Monitor::all()?)
+    }
+
+    pub fn new(monitors: &[Monitor]) -> XCapResult<(MultiMonitorRecorder, Receiver<Frame>)> {
+        MultiMonitorRecorder::with_options(monitors, &RecorderOptions::default())
+    }
+
+    /// `output_size` applies to the combined frames, `native_pixel_format` is ignored.
+    pub fn with_options(
+        monitors: &[Monitor],
+        options: &RecorderOptions,
+    ) -> XCapResult<(MultiMonitorRecorder, Receiver<Frame>)> {
+        options.validate()?;
+
+        if monitors.is_empty() {
+            return Err(XCapError::new("No monitors to record"));
+        }
+
+        let mut bounds = Vec::with_capacity(monitors.len());
+        for monitor in monitors {
+            bounds.push((
+                monitor.x()?,
+                monitor.y()?,
+                monitor.width()?,
+                monitor.height()?,
+            ));
+        }
+
+        let left = bounds.iter().map(|bound| bound.0).min().unwrap_or(0);
+        let top = bounds.iter().map(|bound| bound.1).min().unwrap_or(0);
+        let layouts: Vec<SourceLayout> = bounds
+            .iter()
+            .map(|&(x, y, width, height)| SourceLayout {
+                x: (x - left) as u32,
+                y: (y - top) as u32,
+                width,
+                height,
+            })
+            .collect();
+
+        let canvas_width = layouts
+            .iter()
+            .map(|layout| layout.x + layout.width)
+            .max()
+            .unwrap_or(0);
+        let canvas_height = layouts
+            .iter()
+            .map(|layout| layout.y + layout.height)
+            .max()
+            .unwrap_or(0);
+
+        // 合成较慢时丢弃旧帧，避免阻塞各显示器的录制
+        let source_options = RecorderOptions {
+            native_pixel_format: false,
+            output_size: None,
+            channel_capacity: Some(1),
+            backpressure: BackpressurePolicy::DropOldest,
+            ..options.clone()
+        };
+
+        let lifecycle = RecorderLifecycle::new();
+        let (sender, receiver) = FrameSender::new(
+            options.channel_capacity,
+            options.backpressure,
+            lifecycle.events(),
+        );
+        let (source_sender, source_receiver) = mpsc::channel();
+
+        let mut sources = Vec::with_capacity(monitors.len());
+        for (index, monitor) in monitors.iter().enumerate() {
+            let (source, frames) = monitor.video_recorder_with_options(&source_options)?;
+            forward_source(
+                index,
+                frames,
+                source.events()?,
+                source_sender.clone(),
+                lifecycle.events().clone(),
+            );
+            sources.push(source);
+        }
+        drop(source_sender);
+
+        let recorder = MultiMonitorRecorder {
+            inner: Arc::new(MultiMonitorRecorderInner {
+                sources,
+                sender,
+                lifecycle,
+            }),
+        };
+
+        recorder.compose(
+            layouts,
+            Canvas::new(canvas_width, canvas_height, monitors.len()),
+            source_receiver,
+            options.clone(),
+        )?;
+
+        Ok((recorder, receiver))
+    }
+
+    fn compose(
+        &self,
+        layouts: Vec<SourceLayout>,
+        mut canvas: Canvas,
+        source_receiver: Receiver<(usize, Frame)>,
+        options: RecorderOptions,
+    ) -> XCapResult<()> {
+        let sender = self.inner.sender.clone();
+        let lifecycle = self.inner.lifecycle.clone();
+
+        self.inner.lifecycle.spawn(move || {
+            let events = lifecycle.events();
+            let mut frame_interval = FrameInterval::from_options(&options);
+            let mut sequence = 0;
+            let mut format_tracker = FormatTracker::default();
+
+            loop {
+                match source_receiver.recv_timeout(Duration::from_millis(5)) {
+                    Ok((index, frame)) => {
+                        if let Err(err) = canvas.draw(index, &layouts[index], frame) {
+                            log::error!("Failed to draw frame: {err:?}");
+                            events.error(err);
+                        }
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break Ok(()),
+                }
+
+                match lifecycle.state()? {
+                    RecorderState::Running => {}
+                    RecorderState::Closed => break Ok(()),
+                    _ => continue,
+                }
+
+                // 帧率限制内到达的帧合并到下一帧
+                if !canvas.is_ready() || !frame_interval.ready() {
+                    continue;
+                }
+
+                if let Some(frame) = canvas.take_frame(sequence) {
+                    let frame = match options.scale_frame(frame) {
+                        Ok(frame) => frame,
+                        Err(err) => {
+                            log::error!("Failed to scale frame: {err:?}");
+                            events.error(err);
+                            continue;
+                        }
+                    };
+                    format_tracker.update(&frame, options.target_fps, events);
+                    sender.send(frame)?;
+                    sequence += 1;
+                }
+            }
+        })
+    }
+}
+
+impl MultiMonitorRecorder {
+    pub fn start(&self) -> XCapResult<()> {
+        if self.inner.lifecycle.start()? {
+            for source in &self.inner.sources {
+                source.start()?;
+            }
+        }
+
+        Ok(())
+    }
+    pub fn stop(&self) -> XCapResult<()> {
+        self.inner.lifecycle.stop()?;
+        for source in &self.inner.sources {
+            source.stop()?;
+        }
+
+        Ok(())
+    }
+    /// Stop capturing every monitor, the receiver gets no more frames.
+    pub fn close(&self) -> XCapResult<()> {
+        self.inner.close()
+    }
+    pub fn state(&self) -> XCapResult<RecorderState> {
+        self.inner.lifecycle.state()
+    }
+    /// A receiver of the events emitted from now on, each call returns a new one.
+    pub fn events(&self) -> XCapResult<Receiver<RecorderEvent>> {
+        self.inner.lifecycle.events().subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::frame_at;
+
+    #[test]
+    fn test_canvas_draw() {
+        let mut canvas = Canvas::new(3, 2, 1);
+        let layout = SourceLayout {
+            x: 1,
+            y: 0,
+            width: 2,
+            height: 2,
+        };
+
+        // 2 倍缩放的显示器
+        canvas
+            .draw(0, &layout, frame_at(4, 4, [255, 0, 0, 255], 0))
+            .unwrap();
+        assert!(canvas.is_ready());
+
+        let frame = canvas.take_frame(7).unwrap();
+        assert_eq!((frame.width, frame.height, frame.sequence), (3, 2, 7));
+        assert_eq!(&frame.raw[..4], &[0, 0, 0, 0]);
+        assert_eq!(&frame.raw[4..12], &[255, 0, 0, 255].repeat(2)[..]);
+        assert_eq!(
+            frame.dirty_rects,
+            vec![DirtyRect {
+                x: 1,
+                y: 0,
+                width: 2,
+                height: 2,
+            }]
+        );
+        assert!(canvas.take_frame(8).is_none());
+    }
+
+    #[test]
+    fn test_canvas_timing() {
+        let mut canvas = Canvas::new(2, 1, 2);
+        let left = SourceLayout {
+            x: 0,
+            y: 0,
+            width: 1,
+            height: 1,
+        };
+        let right = SourceLayout { x: 1, ..left };
+        let frame = |color: u8, millis: u64| frame_at(1, 1, [color, 0, 0, 255], millis);
+
+        // 等待每个显示器的第一帧
+        canvas.draw(0, &left, frame(1, 20)).unwrap();
+        assert!(!canvas.is_ready());
+        canvas.draw(1, &right, frame(2, 10)).unwrap();
+        assert!(canvas.is_ready());
+        let first = canvas.take_frame(0).unwrap();
+        assert_eq!(first.timestamp, Duration::from_millis(20));
+
+        // 同一显示器的旧帧被丢弃，时间戳不倒退
+        canvas.draw(0, &left, frame(3, 15)).unwrap();
+        assert!(!canvas.is_ready());
+        canvas.draw(1, &right, frame(4, 12)).unwrap();
+        let second = canvas.take_frame(1).unwrap();
+        assert_eq!(second.raw, [1, 0, 0, 255, 4, 0, 0, 255]);
+        assert_eq!(second.timestamp, Duration::from_millis(20));
+    }
+
+    #[test]
+    fn test_canvas_dirty_rects_limit() {
+        let mut canvas = Canvas::new(40, 1, 1);
+        let layout = SourceLayout {
+            x: 0,
+            y: 0,
+            width: 40,
+            height: 1,
+        };
+
+        // 合成跟不上时变化区域不会无限增长
+        for millis in 0..40 {
+            let dirty_rect = DirtyRect {
+                x: millis as u32,
+                y: 0,
+                width: 1,
+                height: 1,
+            };
+            canvas
+                .draw(
+                    0,
+                    &layout,
+                    frame_at(40, 1, [0, 0, 0, 255], millis).with_dirty_rects(vec![dirty_rect]),
+                )
+                .unwrap();
+        }
+        let frame = canvas.take_frame(0).unwrap();
+        assert_eq!(
+            frame.dirty_rects,
+            vec![DirtyRect {
+                x: 0,
+                y: 0,
+                width: 40,
+                height: 1,
+            }]
+        );
+    }
+}
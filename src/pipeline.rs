@@ -0,0 +1,495 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        mpsc::{self, Receiver, Sender},
+    },
+    thread::{self, JoinHandle},
+};
+
+use crate::{
+    VideoRecorder, XCapError, XCapResult,
+    pixel_format::PixelFormat,
+    video_recorder::{Frame, FrameFormat, RecorderEvent},
+};
+
+/// A consumer of recorder frames, e.g. a file writer.
+pub trait FrameSink: Send {
+    fn on_frame(&mut self, frame: &Frame) -> XCapResult<()>;
+
+    /// Called before the first frame and whenever the size, pixel format or frame rate changes.
+    fn on_format_change(&mut self, _format: FrameFormat) -> XCapResult<()> {
+        Ok(())
+    }
+
+    /// Called once after the last frame.
+    fn finish(&mut self) -> XCapResult<()> {
+        Ok(())
+    }
+}
+
+impl<S: FrameSink + ?Sized> FrameSink for Box<S> {
+    fn on_frame(&mut self, frame: &Frame) -> XCapResult<()> {
+        (**self).on_frame(frame)
+    }
+
+    fn on_format_change(&mut self, format: FrameFormat) -> XCapResult<()> {
+        (**self).on_format_change(format)
+    }
+
+    fn finish(&mut self) -> XCapResult<()> {
+        (**self).finish()
+    }
+}
+
+/// The format of frames after a transform, planar formats become RGBA
+fn transformed_format(format: FrameFormat, width: u32, height: u32) -> FrameFormat {
+    FrameFormat {
+        width,
+        height,
+        pixel_format: if format.pixel_format.is_planar() {
+            PixelFormat::Rgba
+        } else {
+            format.pixel_format
+        },
+        fps: format.fps,
+    }
+}
+
+/// Scales frames to a fixed size before passing them on.
+#[derive(Debug)]
+pub struct ScaleSink<S: FrameSink> {
+    width: u32,
+    height: u32,
+    sink: S,
+}
+
+impl<S: FrameSink> ScaleSink<S> {
+    pub fn new(width: u32, height: u32, sink: S) -> Self {
+        ScaleSink {
+            width,
+            height,
+            sink,
+        }
+    }
+}
+
+impl<S: FrameSink> FrameSink for ScaleSink<S> {
+    fn on_frame(&mut self, frame: &Frame) -> XCapResult<()> {
+        if (frame.width, frame.height) == (self.width, self.height) {
+            return self.sink.on_frame(frame);
+        }
+
+        self.sink.on_frame(&frame.resize(self.width, self.height)?)
+    }
+
+    fn on_format_change(&mut self, format: FrameFormat) -> XCapResult<()> {
+        self.sink
+            .on_format_change(transformed_format(format, self.width, self.height))
+    }
+
+    fn finish(&mut self) -> XCapResult<()> {
+        self.sink.finish()
+    }
+}
+
+/// Cuts a rectangle out of the frames before passing them on.
+#[derive(Debug)]
+pub struct CropSink<S: FrameSink> {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    sink: S,
+}
+
+impl<S: FrameSink> CropSink<S> {
+    pub fn new(x: u32, y: u32, width: u32, height: u32, sink: S) -> Self {
+        CropSink {
+            x,
+            y,
+            width,
+            height,
+            sink,
+        }
+    }
+}
+
+impl<S: FrameSink> FrameSink for CropSink<S> {
+    fn on_frame(&mut self, frame: &Frame) -> XCapResult<()> {
+        self.sink
+            .on_frame(&frame.crop(self.x, self.y, self.width, self.height)?)
+    }
+
+    fn on_format_change(&mut self, format: FrameFormat) -> XCapResult<()> {
+        self.sink
+            .on_format_change(transformed_format(format, self.width, self.height))
+    }
+
+    fn finish(&mut self) -> XCapResult<()> {
+        self.sink.finish()
+    }
+}
+
+/// Passes every frame to several sinks on the same thread.
+///
+/// All sinks get every call even when one fails, the first error is returned.
+#[derive(Default)]
+pub struct TeeSink {
+    sinks: Vec<Box<dyn FrameSink>>,
+}
+
+impl TeeSink {
+    pub fn new() -> Self {
+        TeeSink::default()
+    }
+
+    pub fn sink<S: FrameSink + 'static>(mut self, sink: S) -> Self {
+        self.sinks.push(Box::new(sink));
+        self
+    }
+
+    fn for_each<F>(&mut self, mut f: F) -> XCapResult<()>
+    where
+        F: FnMut(&mut dyn FrameSink) -> XCapResult<()>,
+    {
+        let mut result = Ok(());
+        for sink in &mut self.sinks {
+            if let Err(err) = f(sink.as_mut()) {
+                if result.is_ok() {
+                    result = Err(err);
+                } else {
+                    log::error!("Frame sink failed: {err:?}");
+                }
+            }
+        }
+
+        result
+    }
+}
+
+impl FrameSink for TeeSink {
+    fn on_frame(&mut self, frame: &Frame) -> XCapResult<()> {
+        self.for_each(|sink| sink.on_frame(frame))
+    }
+
+    fn on_format_change(&mut self, format: FrameFormat) -> XCapResult<()> {
+        self.for_each(|sink| sink.on_format_change(format))
+    }
+
+    fn finish(&mut self) -> XCapResult<()> {
+        self.for_each(|sink| sink.finish())
+    }
+}
+
+enum SinkMessage {
+    Format(FrameFormat),
+    Frame(Arc<Frame>),
+}
+
+/// The dispatcher's end of a sink thread
+struct SinkOutput {
+    sender: Sender<SinkMessage>,
+    /// Frames sent but not yet taken by the sink
+    queued: Arc<AtomicUsize>,
+    dropped: Arc<AtomicU64>,
+    /// The sink thread has stopped, nothing more is sent to it
+    closed: bool,
+}
+
+fn run_sink(
+    mut sink: Box<dyn FrameSink>,
+    receiver: Receiver<SinkMessage>,
+    queued: Arc<AtomicUsize>,
+) -> XCapResult<()> {
+    let result = receiver.iter().try_for_each(|message| match message {
+        SinkMessage::Format(format) => sink.on_format_change(format),
+        SinkMessage::Frame(frame) => {
+            queued.fetch_sub(1, Ordering::Relaxed);
+            sink.on_frame(&frame)
+        }
+    });
+
+    // 出错后也要结束 sink，让写入器写完文件尾
+    let finish_result = sink.finish();
+    match (result, finish_result) {
+        (Err(err), Err(finish_err)) => {
+            log::error!("Failed to finish frame sink: {finish_err:?}");
+            Err(err)
+        }
+        (result, finish_result) => result.and(finish_result),
+    }
+}
+
+/// Connects a recorder to sinks, each running on its own thread.
+///
+/// A sink that falls more than `queue_size` frames behind misses frames instead of slowing
+/// down the others. Sinks finish once the recorder is closed and dropped, or right after
+/// their first error, the other sinks keep running.
+pub struct Pipeline {
+    sinks: Vec<Box<dyn FrameSink>>,
+    queue_size: usize,
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Pipeline {
+            sinks: Vec::new(),
+            queue_size: 8,
+        }
+    }
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Pipeline::default()
+    }
+
+    /// Frames waiting for a sink before new frames are dropped for it, 8 by default.
+    pub fn queue_size(mut self, queue_size: usize) -> Self {
+        self.queue_size = queue_size;
+        self
+    }
+
+    pub fn sink<S: FrameSink + 'static>(mut self, sink: S) -> Self {
+        self.sinks.push(Box::new(sink));
+        self
+    }
+
+    /// Feed the frames of `recorder` to the sinks, call it before starting the recorder.
+    pub fn run(
+        self,
+        recorder: &VideoRecorder,
+        frames: Receiver<Frame>,
+    ) -> XCapResult<PipelineHandle> {
+        if self.sinks.is_empty() {
+            return Err(XCapError::new("No sinks to run"));
+        }
+
+        Ok(self.spawn(frames, recorder.events()?))
+    }
+
+    fn spawn(self, frames: Receiver<Frame>, events: Receiver<RecorderEvent>) -> PipelineHandle {
+        let queue_size = self.queue_size.max(1);
+        let mut outputs = Vec::with_capacity(self.sinks.len());
+        let mut sinks = Vec::with_capacity(self.sinks.len());
+
+        for sink in self.sinks {
+            let (sender, receiver) = mpsc::channel();
+            let queued = Arc::new(AtomicUsize::new(0));
+            let dropped = Arc::new(AtomicU64::new(0));
+
+            let sink_queued = queued.clone();
+            sinks.push((
+                thread::spawn(move || run_sink(sink, receiver, sink_queued)),
+                dropped.clone(),
+            ));
+            outputs.push(SinkOutput {
+                sender,
+                queued,
+                dropped,
+                closed: false,
+            });
+        }
+
+        let dispatcher = thread::spawn(move || {
+            let mut fps = None;
+            let mut last_format = None;
+
+            for frame in frames {
+                for event in events.try_iter() {
+                    if let RecorderEvent::FormatNegotiated(format) = event {
+                        fps = format.fps;
+                    }
+                }
+
+                // 缩放后的帧大小可能与协商的格式不同，以帧为准
+                let format = FrameFormat {
+                    width: frame.width,
+                    height: frame.height,
+                    pixel_format: frame.pixel_format,
+                    fps,
+                };
+                let format_changed = last_format != Some(format);
+                last_format = Some(format);
+
+                let frame = Arc::new(frame);
+                for output in &mut outputs {
+                    // sink 线程出错退出后不再发送
+                    if output.closed {
+                        continue;
+                    }
+
+                    if format_changed && output.sender.send(SinkMessage::Format(format)).is_err() {
+                        output.closed = true;
+                        continue;
+                    }
+
+                    // 队列已满时只丢弃这个输出的帧，不影响其它输出
+                    if output.queued.load(Ordering::Relaxed) >= queue_size {
+                        output.dropped.fetch_add(1, Ordering::Relaxed);
+                        continue;
+                    }
+
+                    output.queued.fetch_add(1, Ordering::Relaxed);
+                    if output
+                        .sender
+                        .send(SinkMessage::Frame(frame.clone()))
+                        .is_err()
+                    {
+                        output.closed = true;
+                    }
+                }
+            }
+        });
+
+        PipelineHandle { dispatcher, sinks }
+    }
+}
+
+/// The threads of a running [`Pipeline`].
+pub struct PipelineHandle {
+    dispatcher: JoinHandle<()>,
+    sinks: Vec<(JoinHandle<XCapResult<()>>, Arc<AtomicU64>)>,
+}
+
+impl PipelineHandle {
+    /// Frames each sink missed because it fell behind, in the order the sinks were added.
+    pub fn dropped_frames(&self) -> Vec<u64> {
+        self.sinks
+            .iter()
+            .map(|(_, dropped)| dropped.load(Ordering::Relaxed))
+            .collect()
+    }
+
+    /// Wait until the recorder is dropped and every sink finished, returns the first error.
+    pub fn join(self) -> XCapResult<()> {
+        self.dispatcher
+            .join()
+            .map_err(|_| XCapError::new("Pipeline thread panicked"))?;
+
+        let mut result = Ok(());
+        for (sink, _) in self.sinks {
+            let sink_result = sink
+                .join()
+                .map_err(|_| XCapError::new("Frame sink thread panicked"))
+                .and_then(|sink_result| sink_result);
+
+            if let Err(err) = sink_result {
+                if result.is_ok() {
+                    result = Err(err);
+                } else {
+                    log::error!("Frame sink failed: {err:?}");
+                }
+            }
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct CollectSink(Arc<Mutex<Vec<String>>>);
+
+    impl FrameSink for CollectSink {
+        fn on_frame(&mut self, frame: &Frame) -> XCapResult<()> {
+            self.0.lock()?.push(format!(
+                "frame {}x{} {}",
+                frame.width, frame.height, frame.raw[0]
+            ));
+            Ok(())
+        }
+
+        fn on_format_change(&mut self, format: FrameFormat) -> XCapResult<()> {
+            self.0
+                .lock()?
+                .push(format!("format {}x{}", format.width, format.height));
+            Ok(())
+        }
+
+        fn finish(&mut self) -> XCapResult<()> {
+            self.0.lock()?.push("finish".to_string());
+            Ok(())
+        }
+    }
+
+    struct FailSink(CollectSink);
+
+    impl FrameSink for FailSink {
+        fn on_frame(&mut self, _frame: &Frame) -> XCapResult<()> {
+            Err(XCapError::new("Sink failed"))
+        }
+
+        fn finish(&mut self) -> XCapResult<()> {
+            self.0.finish()
+        }
+    }
+
+    #[test]
+    fn test_pipeline_sink_error() {
+        let (failing, working) = (CollectSink::default(), CollectSink::default());
+        let (frame_sender, frames) = mpsc::channel();
+        let (_event_sender, events) = mpsc::channel();
+
+        let handle = Pipeline::new()
+            .sink(FailSink(failing.clone()))
+            .sink(working.clone())
+            .spawn(frames, events);
+
+        for color in [1, 2, 3] {
+            frame_sender
+                .send(Frame::new(1, 1, vec![color, 0, 0, 255]))
+                .unwrap();
+        }
+        drop(frame_sender);
+
+        assert!(handle.join().is_err());
+        assert_eq!(*failing.0.lock().unwrap(), ["finish"]);
+        assert_eq!(
+            *working.0.lock().unwrap(),
+            [
+                "format 1x1",
+                "frame 1x1 1",
+                "frame 1x1 2",
+                "frame 1x1 3",
+                "finish"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pipeline_transforms() {
+        let (scaled, cropped) = (CollectSink::default(), CollectSink::default());
+        let (frame_sender, frames) = mpsc::channel();
+        let (_event_sender, events) = mpsc::channel();
+
+        let handle = Pipeline::new()
+            .sink(
+                TeeSink::new()
+                    .sink(ScaleSink::new(2, 2, scaled.clone()))
+                    .sink(CropSink::new(3, 3, 1, 1, cropped.clone())),
+            )
+            .spawn(frames, events);
+
+        let mut raw = [10, 0, 0, 255].repeat(16);
+        raw[15 * 4] = 20;
+        frame_sender.send(Frame::new(4, 4, raw)).unwrap();
+        drop(frame_sender);
+        handle.join().unwrap();
+
+        assert_eq!(
+            *scaled.0.lock().unwrap(),
+            ["format 2x2", "frame 2x2 10", "finish"]
+        );
+        assert_eq!(
+            *cropped.0.lock().unwrap(),
+            ["format 1x1", "frame 1x1 20", "finish"]
+        );
+    }
+}
@@ -0,0 +1,245 @@
+use std::borrow::Cow;
+
+use crate::{
+    encoder::yuv::{YuvConverter, YuvMatrix},
+    error::{XCapError, XCapResult},
+};
+
+/// The layout of a frame's raw bytes, 8 bits per channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum PixelFormat {
+    #[default]
+    Rgba,
+    Bgra,
+    /// Like `Rgba`, but the fourth byte is undefined.
+    Rgbx,
+    /// Like `Bgra`, but the fourth byte is undefined.
+    Bgrx,
+    Rgb,
+    Bgr,
+    /// A Y plane followed by an interleaved UV plane, chroma subsampled 2x2.
+    Nv12,
+    /// Y, U and V planes, chroma subsampled 2x2.
+    I420,
+}
+
+impl PixelFormat {
+    /// Bytes per pixel of packed formats, `None` for planar formats.
+    pub fn bytes_per_pixel(&self) -> Option<u32> {
+        match self {
+            PixelFormat::Rgba | PixelFormat::Bgra | PixelFormat::Rgbx | PixelFormat::Bgrx => {
+                Some(4)
+            }
+            PixelFormat::Rgb | PixelFormat::Bgr => Some(3),
+            PixelFormat::Nv12 | PixelFormat::I420 => None,
+        }
+    }
+
+    pub fn is_planar(&self) -> bool {
+        self.bytes_per_pixel().is_none()
+    }
+
+    pub fn has_alpha(&self) -> bool {
+        matches!(self, PixelFormat::Rgba | PixelFormat::Bgra)
+    }
+
+    /// Bytes per row of a tightly packed image, of the Y plane for planar formats.
+    pub fn stride(&self, width: u32) -> u32 {
+        width * self.bytes_per_pixel().unwrap_or(1)
+    }
+
+    /// Size in bytes of a tightly packed `width`x`height` image.
+    pub fn frame_size(&self, width: u32, height: u32) -> usize {
+        let (width, height) = (width as usize, height as usize);
+
+        match self.bytes_per_pixel() {
+            Some(bytes_per_pixel) => width * height * bytes_per_pixel as usize,
+            None => {
+                let (chroma_width, chroma_height) = chroma_size(width, height);
+                width * height + chroma_width * chroma_height * 2
+            }
+        }
+    }
+
+    /// Offsets of the red, green, blue and alpha bytes of packed formats
+    fn channel_offsets(&self) -> Option<(usize, usize, usize, Option<usize>)> {
+        match self {
+            PixelFormat::Rgba => Some((0, 1, 2, Some(3))),
+            PixelFormat::Bgra => Some((2, 1, 0, Some(3))),
+            PixelFormat::Rgbx | PixelFormat::Rgb => Some((0, 1, 2, None)),
+            PixelFormat::Bgrx | PixelFormat::Bgr => Some((2, 1, 0, None)),
+            PixelFormat::Nv12 | PixelFormat::I420 => None,
+        }
+    }
+}
+
+fn chroma_size(width: usize, height: usize) -> (usize, usize) {
+    (width.div_ceil(2), height.div_ceil(2))
+}
+
+// BT.601 limited range
+fn yuv_to_rgb(y: u8, u: u8, v: u8) -> [u8; 3] {
+    let c = (y as i32 - 16) * 298;
+    let d = u as i32 - 128;
+    let e = v as i32 - 128;
+
+    [
+        ((c + 409 * e + 128) >> 8).clamp(0, 255) as u8,
+        ((c - 100 * d - 208 * e + 128) >> 8).clamp(0, 255) as u8,
+        ((c + 516 * d + 128) >> 8).clamp(0, 255) as u8,
+    ]
+}
+
+/// Convert tightly packed `raw` bytes in `pixel_format` to RGBA
+pub(crate) fn to_rgba(
+    pixel_format: PixelFormat,
+    width: u32,
+    height: u32,
+    raw: &[u8],
+) -> XCapResult<Cow<'_, [u8]>> {
+    let frame_size = pixel_format.frame_size(width, height);
+    if raw.len() < frame_size {
+        return Err(XCapError::new(format!(
+            "Frame data is too short for {width}x{height} {pixel_format:?}"
+        )));
+    }
+
+    if pixel_format == PixelFormat::Rgba {
+        return Ok(Cow::Borrowed(&raw[..frame_size]));
+    }
+
+    let (width, height) = (width as usize, height as usize);
+    let mut rgba = vec![0u8; width * height * 4];
+
+    if let (Some(bytes_per_pixel), Some((r, g, b, a))) = (
+        pixel_format.bytes_per_pixel(),
+        pixel_format.channel_offsets(),
+    ) {
+        for (src, dst) in raw
+            .chunks_exact(bytes_per_pixel as usize)
+            .zip(rgba.chunks_exact_mut(4))
+        {
+            dst[0] = src[r];
+            dst[1] = src[g];
+            dst[2] = src[b];
+            dst[3] = a.map_or(255, |a| src[a]);
+        }
+
+        return Ok(Cow::Owned(rgba));
+    }
+
+    let (chroma_width, chroma_height) = chroma_size(width, height);
+    let (y_plane, chroma_planes) = raw.split_at(width * height);
+
+    for y in 0..height {
+        for x in 0..width {
+            let chroma_index = (y / 2) * chroma_width + x / 2;
+            let (u, v) = match pixel_format {
+                PixelFormat::Nv12 => (
+                    chroma_planes[chroma_index * 2],
+                    chroma_planes[chroma_index * 2 + 1],
+                ),
+                _ => (
+                    chroma_planes[chroma_index],
+                    chroma_planes[chroma_width * chroma_height + chroma_index],
+                ),
+            };
+
+            let index = (y * width + x) * 4;
+            rgba[index..index + 3].copy_from_slice(&yuv_to_rgb(y_plane[y * width + x], u, v));
+            rgba[index + 3] = 255;
+        }
+    }
+
+    Ok(Cow::Owned(rgba))
+}
+
+/// Convert tightly packed RGBA bytes to `pixel_format`
+pub(crate) fn from_rgba(
+    pixel_format: PixelFormat,
+    width: u32,
+    height: u32,
+    rgba: &[u8],
+) -> Vec<u8> {
+    if pixel_format == PixelFormat::Rgba {
+        return rgba.to_vec();
+    }
+
+    let (width, height) = (width as usize, height as usize);
+
+    if let (Some(bytes_per_pixel), Some((r, g, b, a))) = (
+        pixel_format.bytes_per_pixel(),
+        pixel_format.channel_offsets(),
+    ) {
+        let bytes_per_pixel = bytes_per_pixel as usize;
+        let mut raw = vec![255u8; width * height * bytes_per_pixel];
+
+        for (src, dst) in rgba
+            .chunks_exact(4)
+            .zip(raw.chunks_exact_mut(bytes_per_pixel))
+        {
+            dst[r] = src[0];
+            dst[g] = src[1];
+            dst[b] = src[2];
+            if let Some(a) = a {
+                dst[a] = src[3];
+            }
+        }
+
+        return raw;
+    }
+
+    // 与 to_rgba 一样使用 BT.601，奇数宽高时边缘的色度只取 1 或 2 个像素的平均值
+    let mut raw =
+        YuvConverter::new(YuvMatrix::Bt601, true).convert(width as u32, height as u32, rgba);
+
+    // NV12 的 U、V 交错存放在同一个平面
+    if pixel_format == PixelFormat::Nv12 {
+        let (chroma_width, chroma_height) = chroma_size(width, height);
+        let chroma_planes = raw.split_off(width * height);
+        let (u_plane, v_plane) = chroma_planes.split_at(chroma_width * chroma_height);
+        raw.extend(u_plane.iter().zip(v_plane).flat_map(|(u, v)| [*u, *v]));
+    }
+
+    raw
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packed_round_trip() {
+        let rgba = [10, 20, 30, 40, 50, 60, 70, 80];
+
+        let bgra = from_rgba(PixelFormat::Bgra, 2, 1, &rgba);
+        assert_eq!(bgra, [30, 20, 10, 40, 70, 60, 50, 80]);
+        assert_eq!(to_rgba(PixelFormat::Bgra, 2, 1, &bgra).unwrap(), &rgba[..]);
+
+        let bgrx = [30, 20, 10, 0, 70, 60, 50, 0];
+        assert_eq!(
+            to_rgba(PixelFormat::Bgrx, 2, 1, &bgrx).unwrap(),
+            &[10, 20, 30, 255, 50, 60, 70, 255][..]
+        );
+    }
+
+    #[test]
+    fn test_yuv_odd_dimensions() {
+        let rgba = [200, 100, 50, 255].repeat(3 * 3);
+
+        for pixel_format in [PixelFormat::Nv12, PixelFormat::I420] {
+            let raw = from_rgba(pixel_format, 3, 3, &rgba);
+            assert_eq!(raw.len(), 3 * 3 + 2 * 2 * 2);
+
+            let decoded = to_rgba(pixel_format, 3, 3, &raw).unwrap();
+            for (src, dst) in rgba.iter().zip(decoded.iter()) {
+                assert!(src.abs_diff(*dst) <= 2);
+            }
+        }
+    }
+
+    #[test]
+    fn test_short_data() {
+        assert!(to_rgba(PixelFormat::I420, 4, 4, &[0; 16]).is_err());
+    }
+}
@@ -0,0 +1,20 @@
+use std::{path::PathBuf, time::SystemTime};
+
+/// Details of the process that owns a window, see [`crate::Window::process`].
+#[derive(Debug, Clone)]
+pub struct ProcessInfo {
+    /// The process id
+    pub pid: u32,
+    /// The parent process id
+    pub parent_pid: u32,
+    /// The process name
+    pub name: String,
+    /// The executable path, `None` if it is not readable by the current user
+    pub exe: Option<PathBuf>,
+    /// The command line arguments, including the program name
+    pub cmdline: Vec<String>,
+    /// The time the process was started
+    pub start_time: SystemTime,
+    /// The real user id of the process
+    pub uid: u32,
+}
@@ -0,0 +1,323 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use image::{
+    ImageEncoder,
+    codecs::{
+        jpeg::JpegEncoder,
+        png::{CompressionType, FilterType, PngEncoder},
+    },
+};
+
+use crate::{
+    error::{XCapError, XCapResult},
+    pipeline::FrameSink,
+    pixel_format::PixelFormat,
+    video_recorder::{DirtyRect, Frame},
+};
+
+/// How [`ReplayBuffer`] stores frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReplayCompression {
+    /// Frames are kept as captured, the fastest but uses the most memory.
+    #[default]
+    None,
+    /// Lossless PNG, frames come back as RGBA.
+    Png,
+    /// Lossy JPEG with the given quality from 1 to 100, frames come back as RGBA.
+    Jpeg(u8),
+}
+
+/// Options for [`ReplayBuffer::new`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayOptions {
+    /// How far back frames are kept, relative to the latest frame.
+    pub max_duration: Duration,
+    /// The most memory the stored frames may use, the oldest frames are dropped first.
+    pub max_bytes: usize,
+    pub compression: ReplayCompression,
+}
+
+impl Default for ReplayOptions {
+    fn default() -> Self {
+        ReplayOptions {
+            max_duration: Duration::from_secs(30),
+            max_bytes: 512 * 1024 * 1024,
+            compression: ReplayCompression::None,
+        }
+    }
+}
+
+/// A stored frame, `frame.raw` is empty when the pixels are compressed
+#[derive(Debug, Clone)]
+struct ReplayFrame {
+    frame: Frame,
+    compressed: Option<Vec<u8>>,
+}
+
+impl ReplayFrame {
+    fn size(&self) -> usize {
+        self.frame.raw.len() + self.compressed.as_ref().map_or(0, Vec::len)
+    }
+
+    fn compress(frame: &Frame, compression: ReplayCompression) -> XCapResult<Self> {
+        let compressed = match compression {
+            ReplayCompression::None => {
+                return Ok(ReplayFrame {
+                    frame: frame.clone(),
+                    compressed: None,
+                });
+            }
+            ReplayCompression::Png => {
+                let image = frame.to_rgba_image()?;
+                let mut png = Vec::new();
+                PngEncoder::new_with_quality(&mut png, CompressionType::Fast, FilterType::Adaptive)
+                    .write_image(
+                        image.as_raw(),
+                        image.width(),
+                        image.height(),
+                        image::ExtendedColorType::Rgba8,
+                    )?;
+                png
+            }
+            ReplayCompression::Jpeg(quality) => {
+                let image = frame.to_rgb_image()?;
+                let mut jpeg = Vec::new();
+                JpegEncoder::new_with_quality(&mut jpeg, quality).encode_image(&image)?;
+                jpeg
+            }
+        };
+
+        Ok(ReplayFrame {
+            frame: Frame {
+                width: frame.width,
+                height: frame.height,
+                raw: Vec::new(),
+                pixel_format: PixelFormat::Rgba,
+                stride: frame.width * 4,
+                dirty_rects: frame.dirty_rects.clone(),
+                timestamp: frame.timestamp,
+                system_time: frame.system_time,
+                sequence: frame.sequence,
+            },
+            compressed: Some(compressed),
+        })
+    }
+
+    fn decompress(self) -> XCapResult<Frame> {
+        let Some(compressed) = self.compressed else {
+            return Ok(self.frame);
+        };
+
+        let image = image::load_from_memory(&compressed)?;
+
+        Ok(Frame {
+            raw: image.into_rgba8().into_raw(),
+            ..self.frame
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct ReplayBufferInner {
+    frames: VecDeque<ReplayFrame>,
+    bytes: usize,
+}
+
+/// Keeps the most recent frames of a recording in memory, for saving after something happened.
+///
+/// Clones share the same frames, so one clone can be fed by a [`crate::Pipeline`] while
+/// another is used to [`ReplayBuffer::dump`].
+#[derive(Debug, Clone)]
+pub struct ReplayBuffer {
+    inner: Arc<Mutex<ReplayBufferInner>>,
+    options: ReplayOptions,
+}
+
+impl ReplayBuffer {
+    pub fn new(options: &ReplayOptions) -> XCapResult<Self> {
+        if options.max_duration.is_zero() || options.max_bytes == 0 {
+            return Err(XCapError::new(
+                "Replay buffer duration and size must be greater than 0",
+            ));
+        }
+
+        if let ReplayCompression::Jpeg(quality) = options.compression
+            && !(1..=100).contains(&quality)
+        {
+            return Err(XCapError::new(format!(
+                "JPEG quality {quality} is not in 1..=100"
+            )));
+        }
+
+        Ok(ReplayBuffer {
+            inner: Arc::new(Mutex::new(ReplayBufferInner::default())),
+            options: options.clone(),
+        })
+    }
+
+    /// Store a frame, dropping the frames that fall outside the limits.
+    pub fn push(&self, frame: &Frame) -> XCapResult<()> {
+        // 压缩在锁外进行，避免阻塞 dump
+        let frame = ReplayFrame::compress(frame, self.options.compression)?;
+
+        let mut inner = self.inner.lock()?;
+        inner.bytes += frame.size();
+        let latest = frame.frame.timestamp;
+        inner.frames.push_back(frame);
+
+        // 至少保留最新的一帧
+        while inner.frames.len() > 1 {
+            let oldest = &inner.frames[0];
+            if inner.bytes <= self.options.max_bytes
+                && latest.saturating_sub(oldest.frame.timestamp) <= self.options.max_duration
+            {
+                break;
+            }
+
+            if let Some(oldest) = inner.frames.pop_front() {
+                inner.bytes -= oldest.size();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The frames of the last `duration` before the latest frame, oldest first.
+    ///
+    /// The buffer is left untouched, compressed frames are decoded to RGBA. The first frame
+    /// is marked dirty as a whole, since the frames it was diffed against are not included.
+    pub fn dump(&self, duration: Duration) -> XCapResult<Vec<Frame>> {
+        let frames: Vec<ReplayFrame> = {
+            let inner = self.inner.lock()?;
+            let Some(latest) = inner.frames.back() else {
+                return Ok(Vec::new());
+            };
+            let start = latest.frame.timestamp.saturating_sub(duration);
+
+            inner
+                .frames
+                .iter()
+                .filter(|frame| frame.frame.timestamp >= start)
+                .cloned()
+                .collect()
+        };
+
+        let mut frames = frames
+            .into_iter()
+            .map(ReplayFrame::decompress)
+            .collect::<XCapResult<Vec<Frame>>>()?;
+
+        if let Some(first) = frames.first_mut() {
+            first.dirty_rects = vec![DirtyRect {
+                x: 0,
+                y: 0,
+                width: first.width,
+                height: first.height,
+            }];
+        }
+
+        Ok(frames)
+    }
+
+    pub fn clear(&self) -> XCapResult<()> {
+        let mut inner = self.inner.lock()?;
+        inner.frames.clear();
+        inner.bytes = 0;
+
+        Ok(())
+    }
+
+    pub fn len(&self) -> XCapResult<usize> {
+        Ok(self.inner.lock()?.frames.len())
+    }
+
+    pub fn is_empty(&self) -> XCapResult<bool> {
+        Ok(self.inner.lock()?.frames.is_empty())
+    }
+
+    /// The memory used by the stored frames.
+    pub fn bytes(&self) -> XCapResult<usize> {
+        Ok(self.inner.lock()?.bytes)
+    }
+}
+
+impl FrameSink for ReplayBuffer {
+    fn on_frame(&mut self, frame: &Frame) -> XCapResult<()> {
+        self.push(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::frame_at;
+
+    fn frame(color: u8, secs: u64) -> Frame {
+        frame_at(4, 2, [color, 1, 2, 255], secs * 1000)
+    }
+
+    #[test]
+    fn test_replay_buffer_limits() {
+        let buffer = ReplayBuffer::new(&ReplayOptions {
+            max_duration: Duration::from_secs(2),
+            ..Default::default()
+        })
+        .unwrap();
+        for secs in 0..5 {
+            buffer.push(&frame(secs as u8, secs)).unwrap();
+        }
+        assert_eq!(buffer.len().unwrap(), 3);
+        assert_eq!(buffer.bytes().unwrap(), 3 * 32);
+
+        let dumped = buffer.dump(Duration::from_secs(1)).unwrap();
+        let colors: Vec<u8> = dumped.iter().map(|frame| frame.raw[0]).collect();
+        assert_eq!(colors, [3, 4]);
+
+        // 超出字节上限时只保留最新的帧
+        let buffer = ReplayBuffer::new(&ReplayOptions {
+            max_bytes: 40,
+            compression: ReplayCompression::Png,
+            ..Default::default()
+        })
+        .unwrap();
+        buffer.push(&frame(7, 0)).unwrap();
+        buffer.push(&frame(9, 1)).unwrap();
+        assert_eq!(buffer.len().unwrap(), 1);
+
+        let dumped = buffer.dump(Duration::from_secs(60)).unwrap();
+        assert_eq!(dumped[0].raw, [9, 1, 2, 255].repeat(8));
+        assert_eq!(dumped[0].timestamp, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_dump_first_frame_dirty() {
+        let buffer = ReplayBuffer::new(&ReplayOptions::default()).unwrap();
+        let dirty_rect = DirtyRect {
+            x: 1,
+            y: 0,
+            width: 2,
+            height: 1,
+        };
+        for secs in 0..3 {
+            buffer
+                .push(&frame(secs as u8, secs).with_dirty_rects(vec![dirty_rect]))
+                .unwrap();
+        }
+
+        let dumped = buffer.dump(Duration::from_secs(1)).unwrap();
+        assert_eq!(
+            dumped[0].dirty_rects,
+            [DirtyRect {
+                x: 0,
+                y: 0,
+                width: 4,
+                height: 2,
+            }]
+        );
+        assert_eq!(dumped[1].dirty_rects, [dirty_rect]);
+    }
+}
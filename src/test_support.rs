@@ -0,0 +1,11 @@
+use std::time::Duration;
+
+use crate::video_recorder::Frame;
+
+/// A `width`x`height` frame filled with `rgba`, captured `millis` after the recorder started
+pub(crate) fn frame_at(width: u32, height: u32, rgba: [u8; 4], millis: u64) -> Frame {
+    let mut frame = Frame::new(width, height, rgba.repeat((width * height) as usize));
+    frame.timestamp = Duration::from_millis(millis);
+
+    frame
+}
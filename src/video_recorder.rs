@@ -1,21 +1,513 @@
-use std::sync::{Condvar, Mutex};
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    mem,
+    sync::{
+        Arc, Condvar, LazyLock, Mutex,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender},
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime},
+};
 
-use crate::{XCapResult, platform::impl_video_recorder::ImplVideoRecorder};
+use image::{
+    DynamicImage, RgbImage, RgbaImage,
+    imageops::{self, FilterType},
+};
+
+use crate::{
+    XCapError, XCapResult,
+    pixel_format::{self, PixelFormat},
+    platform::impl_video_recorder::ImplVideoRecorder,
+};
+
+/// A rectangle of a frame, in pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DirtyRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl DirtyRect {
+    /// The overlapping part of two rectangles, `None` when they don't overlap.
+    pub fn intersect(&self, other: &DirtyRect) -> Option<DirtyRect> {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = (self.x + self.width).min(other.x + other.width);
+        let bottom = (self.y + self.height).min(other.y + other.height);
+
+        if right <= x || bottom <= y {
+            return None;
+        }
+
+        Some(DirtyRect {
+            x,
+            y,
+            width: right - x,
+            height: bottom - y,
+        })
+    }
+}
+
+/// What a recorder does with a new frame when its channel is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BackpressurePolicy {
+    /// Wait until the receiver takes a frame.
+    #[default]
+    Block,
+    /// Discard the oldest queued frame.
+    DropOldest,
+}
+
+/// Options for [`crate::Monitor::video_recorder_with_options`] and
+/// [`crate::Window::video_recorder_with_options`].
+#[derive(Debug, Clone, Default)]
+pub struct RecorderOptions {
+    /// Deliver frames in the backend's native pixel format instead of converting them to RGBA.
+    /// Window recorders, except on Wayland, capture RGBA images and fail to start with it.
+    pub native_pixel_format: bool,
+    /// The frame rate to capture at, the backend's default when `None`.
+    pub target_fps: Option<f32>,
+    /// Frames arriving faster than this are skipped.
+    pub max_fps: Option<f32>,
+    /// Scale frames to this width and height.
+    pub output_size: Option<(u32, u32)>,
+    /// Frames queued for the receiver, the platform's default when `None`.
+    pub channel_capacity: Option<usize>,
+    pub backpressure: BackpressurePolicy,
+}
+
+impl RecorderOptions {
+    pub(crate) fn validate(&self) -> XCapResult<()> {
+        for fps in [self.target_fps, self.max_fps].into_iter().flatten() {
+            if !fps.is_finite() || fps <= 0.0 {
+                return Err(XCapError::new(format!("Invalid frame rate {fps}")));
+            }
+        }
+
+        if let Some((width, height)) = self.output_size
+            && (width == 0 || height == 0)
+        {
+            return Err(XCapError::new("Output size must be greater than 0"));
+        }
+
+        Ok(())
+    }
+
+    /// Scale the frame to `output_size`
+    pub(crate) fn scale_frame(&self, frame: Frame) -> XCapResult<Frame> {
+        match self.output_size {
+            Some((width, height)) if (width, height) != (frame.width, frame.height) => {
+                frame.resize(width, height)
+            }
+            _ => Ok(frame),
+        }
+    }
+}
+
+/// One plane of a frame's raw data, whose rows may be padded to `stride` bytes.
+#[derive(Debug, Clone, Copy)]
+pub struct FramePlane<'a> {
+    pub data: &'a [u8],
+    /// Bytes of pixel data per row, without padding.
+    pub row_size: u32,
+    pub height: u32,
+    pub stride: u32,
+}
+
+impl<'a> FramePlane<'a> {
+    /// The pixel data of row `y`, without padding.
+    pub fn row(&self, y: u32) -> Option<&'a [u8]> {
+        if y >= self.height {
+            return None;
+        }
+
+        let start = y as usize * self.stride as usize;
+        self.data.get(start..start + self.row_size as usize)
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &'a [u8]> + 'a {
+        let plane = *self;
+
+        (0..plane.height).filter_map(move |y| plane.row(y))
+    }
+}
+
+/// Monotonic time since a process-wide origin
+pub(crate) fn monotonic_timestamp() -> Duration {
+    static ORIGIN: LazyLock<Instant> = LazyLock::new(Instant::now);
+
+    ORIGIN.elapsed()
+}
 
 #[derive(Debug, Clone)]
 pub struct Frame {
     pub width: u32,
     pub height: u32,
     pub raw: Vec<u8>,
+    /// The layout of `raw`, RGBA unless the recorder was asked for the native format.
+    pub pixel_format: PixelFormat,
+    /// Bytes per row in `raw` including padding, of the Y plane for planar formats.
+    pub stride: u32,
+    /// The regions changed since the previous frame, the whole frame when unknown and empty
+    /// when nothing changed.
+    pub dirty_rects: Vec<DirtyRect>,
+    /// Monotonic capture time, relative to an arbitrary process-wide origin.
+    pub timestamp: Duration,
+    /// Wall-clock capture time.
+    pub system_time: SystemTime,
+    /// The frame number within its recording, starting at 0.
+    pub sequence: u64,
 }
 
 impl Frame {
     pub fn new(width: u32, height: u32, raw: Vec<u8>) -> Self {
-        Self { width, height, raw }
+        Self {
+            width,
+            height,
+            raw,
+            pixel_format: PixelFormat::Rgba,
+            stride: width * 4,
+            dirty_rects: vec![DirtyRect {
+                x: 0,
+                y: 0,
+                width,
+                height,
+            }],
+            timestamp: monotonic_timestamp(),
+            system_time: SystemTime::now(),
+            sequence: 0,
+        }
+    }
+
+    /// Set the pixel format, and the stride to that of tightly packed rows.
+    pub fn with_pixel_format(mut self, pixel_format: PixelFormat) -> Self {
+        self.pixel_format = pixel_format;
+        self.stride = pixel_format.stride(self.width);
+
+        self
+    }
+
+    pub fn with_stride(mut self, stride: u32) -> Self {
+        self.stride = stride;
+
+        self
+    }
+
+    pub fn with_dirty_rects(mut self, dirty_rects: Vec<DirtyRect>) -> Self {
+        if !dirty_rects.is_empty() {
+            self.dirty_rects = dirty_rects;
+        }
+
+        self
+    }
+
+    pub fn with_sequence(mut self, sequence: u64) -> Self {
+        self.sequence = sequence;
+
+        self
+    }
+
+    /// Add the dirty rects of an earlier frame that will never be delivered, so they are
+    /// not lost. The whole frame becomes dirty when the sizes differ.
+    pub(crate) fn merge_dirty_rects(&mut self, dropped: &Frame) {
+        // 矩形过多时不如直接整帧重绘
+        if (dropped.width, dropped.height) != (self.width, self.height)
+            || self.dirty_rects.len() + dropped.dirty_rects.len() > 32
+        {
+            self.dirty_rects = vec![DirtyRect {
+                x: 0,
+                y: 0,
+                width: self.width,
+                height: self.height,
+            }];
+            return;
+        }
+
+        for dirty_rect in &dropped.dirty_rects {
+            if !self.dirty_rects.contains(dirty_rect) {
+                self.dirty_rects.push(*dirty_rect);
+            }
+        }
+    }
+
+    /// Move the capture time back by `delay`, for frames captured before they were received.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn with_capture_delay(mut self, delay: Duration) -> Self {
+        self.timestamp = self.timestamp.saturating_sub(delay);
+        self.system_time = self
+            .system_time
+            .checked_sub(delay)
+            .unwrap_or(self.system_time);
+
+        self
+    }
+
+    /// The planes of `raw`: one for packed formats, Y and UV for NV12, Y, U and V for I420.
+    ///
+    /// Chroma rows of I420 are padded to half the stride, those of NV12 to the stride.
+    pub fn planes(&self) -> XCapResult<Vec<FramePlane<'_>>> {
+        let (width, height, stride) = (self.width, self.height, self.stride);
+        let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+
+        let layouts = match self.pixel_format {
+            PixelFormat::Nv12 => vec![
+                (width, height, stride),
+                (
+                    chroma_width * 2,
+                    chroma_height,
+                    stride.max(chroma_width * 2),
+                ),
+            ],
+            PixelFormat::I420 => vec![
+                (width, height, stride),
+                (chroma_width, chroma_height, stride.div_ceil(2)),
+                (chroma_width, chroma_height, stride.div_ceil(2)),
+            ],
+            pixel_format => vec![(pixel_format.stride(width), height, stride)],
+        };
+
+        let mut offset = 0;
+        let mut planes = Vec::with_capacity(layouts.len());
+
+        for (row_size, height, stride) in layouts {
+            if stride < row_size {
+                return Err(XCapError::new(format!(
+                    "Stride {stride} is smaller than the row size {row_size}"
+                )));
+            }
+
+            // 最后一行可以没有填充
+            let size = match height {
+                0 => 0,
+                height => (stride * (height - 1) + row_size) as usize,
+            };
+            let data = self
+                .raw
+                .get(offset..offset + size)
+                .ok_or(XCapError::new("Frame data is too short"))?;
+
+            planes.push(FramePlane {
+                data,
+                row_size,
+                height,
+                stride,
+            });
+            offset += (stride * height) as usize;
+        }
+
+        Ok(planes)
+    }
+
+    /// The pixel data of row `y` of the first plane, without padding.
+    pub fn row(&self, y: u32) -> Option<&[u8]> {
+        self.planes().ok()?.first()?.row(y)
+    }
+
+    /// `raw` without row padding
+    fn packed_raw(&self) -> XCapResult<Cow<'_, [u8]>> {
+        let planes = self.planes()?;
+
+        if planes.iter().all(|plane| plane.stride == plane.row_size) {
+            let frame_size = self.pixel_format.frame_size(self.width, self.height);
+            return Ok(Cow::Borrowed(&self.raw[..frame_size]));
+        }
+
+        Ok(Cow::Owned(
+            planes
+                .iter()
+                .flat_map(|plane| plane.rows())
+                .flatten()
+                .copied()
+                .collect(),
+        ))
+    }
+
+    /// Convert the frame to another pixel format, YUV formats use BT.601 limited range.
+    pub fn convert(&self, pixel_format: PixelFormat) -> XCapResult<Frame> {
+        let packed_raw = self.packed_raw()?;
+
+        let raw = if self.pixel_format == pixel_format {
+            packed_raw.into_owned()
+        } else {
+            let rgba =
+                pixel_format::to_rgba(self.pixel_format, self.width, self.height, &packed_raw)?;
+            pixel_format::from_rgba(pixel_format, self.width, self.height, &rgba)
+        };
+
+        Ok(Frame {
+            width: self.width,
+            height: self.height,
+            raw,
+            pixel_format,
+            stride: pixel_format.stride(self.width),
+            dirty_rects: self.dirty_rects.clone(),
+            timestamp: self.timestamp,
+            system_time: self.system_time,
+            sequence: self.sequence,
+        })
+    }
+
+    /// Scale the frame, planar formats are converted to RGBA.
+    pub fn resize(&self, width: u32, height: u32) -> XCapResult<Frame> {
+        if width == 0 || height == 0 {
+            return Err(XCapError::new("Frame size must be greater than 0"));
+        }
+
+        let packed_raw = self.packed_raw()?;
+
+        // 打包格式的各个通道互不影响，可以直接按字节缩放
+        let (raw, pixel_format) = match self.pixel_format.bytes_per_pixel() {
+            Some(4) => {
+                let image = RgbaImage::from_raw(self.width, self.height, packed_raw.into_owned())
+                    .ok_or_else(|| XCapError::new("RgbaImage::from_raw failed"))?;
+                let image = imageops::resize(&image, width, height, FilterType::Triangle);
+
+                (image.into_raw(), self.pixel_format)
+            }
+            Some(3) => {
+                let image = RgbImage::from_raw(self.width, self.height, packed_raw.into_owned())
+                    .ok_or_else(|| XCapError::new("RgbImage::from_raw failed"))?;
+                let image = imageops::resize(&image, width, height, FilterType::Triangle);
+
+                (image.into_raw(), self.pixel_format)
+            }
+            _ => {
+                let image =
+                    imageops::resize(&self.to_rgba_image()?, width, height, FilterType::Triangle);
+
+                (image.into_raw(), PixelFormat::Rgba)
+            }
+        };
+
+        // 变化区域向外取整
+        let dirty_rects = self
+            .dirty_rects
+            .iter()
+            .map(|dirty_rect| {
+                let x = dirty_rect.x as u64 * width as u64 / self.width as u64;
+                let y = dirty_rect.y as u64 * height as u64 / self.height as u64;
+                let right = ((dirty_rect.x + dirty_rect.width) as u64 * width as u64)
+                    .div_ceil(self.width as u64);
+                let bottom = ((dirty_rect.y + dirty_rect.height) as u64 * height as u64)
+                    .div_ceil(self.height as u64);
+
+                DirtyRect {
+                    x: x as u32,
+                    y: y as u32,
+                    width: (right - x) as u32,
+                    height: (bottom - y) as u32,
+                }
+            })
+            .collect();
+
+        Ok(Frame {
+            width,
+            height,
+            raw,
+            pixel_format,
+            stride: pixel_format.stride(width),
+            dirty_rects,
+            timestamp: self.timestamp,
+            system_time: self.system_time,
+            sequence: self.sequence,
+        })
+    }
+
+    /// Cut out a rectangle of the frame, planar formats are converted to RGBA.
+    pub fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> XCapResult<Frame> {
+        if width == 0
+            || height == 0
+            || x as u64 + width as u64 > self.width as u64
+            || y as u64 + height as u64 > self.height as u64
+        {
+            return Err(XCapError::new(format!(
+                "Crop ({x}, {y}, {width}, {height}) is outside the {}x{} frame",
+                self.width, self.height
+            )));
+        }
+
+        let Some(bytes_per_pixel) = self.pixel_format.bytes_per_pixel() else {
+            return self.convert(PixelFormat::Rgba)?.crop(x, y, width, height);
+        };
+
+        let start = (x * bytes_per_pixel) as usize;
+        let end = start + (width * bytes_per_pixel) as usize;
+        let mut raw = Vec::with_capacity((end - start) * height as usize);
+        for row in y..y + height {
+            let row = self
+                .row(row)
+                .ok_or_else(|| XCapError::new("Frame data is too short"))?;
+            raw.extend_from_slice(&row[start..end]);
+        }
+
+        let crop_rect = DirtyRect {
+            x,
+            y,
+            width,
+            height,
+        };
+        // 裁剪区域内没有变化时 dirty_rects 为空
+        let dirty_rects: Vec<DirtyRect> = self
+            .dirty_rects
+            .iter()
+            .filter_map(|dirty_rect| dirty_rect.intersect(&crop_rect))
+            .map(|dirty_rect| DirtyRect {
+                x: dirty_rect.x - x,
+                y: dirty_rect.y - y,
+                ..dirty_rect
+            })
+            .collect();
+
+        Ok(Frame {
+            width,
+            height,
+            raw,
+            pixel_format: self.pixel_format,
+            stride: self.pixel_format.stride(width),
+            dirty_rects,
+            timestamp: self.timestamp,
+            system_time: self.system_time,
+            sequence: self.sequence,
+        })
+    }
+
+    pub fn to_rgba_image(&self) -> XCapResult<RgbaImage> {
+        let packed_raw = self.packed_raw()?;
+        let rgba = pixel_format::to_rgba(self.pixel_format, self.width, self.height, &packed_raw)?;
+
+        RgbaImage::from_raw(self.width, self.height, rgba.into_owned())
+            .ok_or_else(|| XCapError::new("RgbaImage::from_raw failed"))
+    }
+
+    pub fn to_rgb_image(&self) -> XCapResult<RgbImage> {
+        let packed_raw = self.packed_raw()?;
+
+        let rgb = if self.pixel_format == PixelFormat::Rgb {
+            packed_raw.into_owned()
+        } else {
+            let rgba =
+                pixel_format::to_rgba(self.pixel_format, self.width, self.height, &packed_raw)?;
+            pixel_format::from_rgba(PixelFormat::Rgb, self.width, self.height, &rgba)
+        };
+
+        RgbImage::from_raw(self.width, self.height, rgb)
+            .ok_or_else(|| XCapError::new("RgbImage::from_raw failed"))
+    }
+
+    /// RGBA for formats with an alpha channel, RGB otherwise.
+    pub fn to_dynamic_image(&self) -> XCapResult<DynamicImage> {
+        if self.pixel_format.has_alpha() {
+            Ok(DynamicImage::ImageRgba8(self.to_rgba_image()?))
+        } else {
+            Ok(DynamicImage::ImageRgb8(self.to_rgb_image()?))
+        }
     }
 }
 
-#[allow(dead_code)]
 #[derive(Debug)]
 pub(crate) struct RecorderWaker {
     parking: Mutex<bool>,
@@ -23,14 +515,12 @@ pub(crate) struct RecorderWaker {
 }
 
 impl RecorderWaker {
-    #[allow(dead_code)]
     pub fn new() -> Self {
         Self {
             parking: Mutex::new(true),
             condvar: Condvar::new(),
         }
     }
-    #[allow(dead_code)]
     pub fn wake(&self) -> XCapResult<()> {
         let mut parking = self.parking.lock()?;
         *parking = false;
@@ -38,14 +528,12 @@ impl RecorderWaker {
 
         Ok(())
     }
-    #[allow(dead_code)]
     pub fn sleep(&self) -> XCapResult<()> {
         let mut parking = self.parking.lock()?;
         *parking = true;
 
         Ok(())
     }
-    #[allow(dead_code)]
     pub fn wait(&self) -> XCapResult<()> {
         let mut parking = self.parking.lock()?;
         while *parking {
@@ -56,24 +544,924 @@ impl RecorderWaker {
     }
 }
 
+#[derive(Debug, Default)]
+struct FrameQueueState {
+    frames: VecDeque<Frame>,
+    /// 转发线程取出但接收端还没拿走的帧，同样占用容量
+    forwarding: bool,
+    /// 上次送达后丢弃的帧数
+    dropped: u64,
+}
+
+impl FrameQueueState {
+    fn len(&self) -> usize {
+        self.frames.len() + self.forwarding as usize
+    }
+}
+
+#[derive(Debug)]
+struct FrameQueue {
+    state: Mutex<FrameQueueState>,
+    condvar: Condvar,
+    capacity: usize,
+    closed: AtomicBool,
+    events: RecorderEvents,
+}
+
+impl FrameQueue {
+    fn close(&self) {
+        // 持有锁再通知，避免等待中的一方错过唤醒
+        let _state = self.state.lock();
+        self.closed.store(true, Ordering::Relaxed);
+        self.condvar.notify_all();
+    }
+
+    /// Create a queue and the thread moving its frames to the returned receiver
+    fn spawn(capacity: usize, events: &RecorderEvents) -> (Arc<FrameQueueHandle>, Receiver<Frame>) {
+        // 接收端无法从发送端清理，所以由转发线程从队列中取帧
+        let (tx, rx) = mpsc::sync_channel(0);
+        let frame_queue = Arc::new(FrameQueue {
+            state: Mutex::new(FrameQueueState::default()),
+            condvar: Condvar::new(),
+            capacity: capacity.max(1),
+            closed: AtomicBool::new(false),
+            events: events.clone(),
+        });
+
+        let queue = frame_queue.clone();
+        thread::spawn(move || {
+            loop {
+                let next = {
+                    let Ok(mut state) = queue.state.lock() else {
+                        break;
+                    };
+
+                    loop {
+                        if let Some(frame) = state.frames.pop_front() {
+                            state.forwarding = true;
+                            break Some((frame, mem::take(&mut state.dropped)));
+                        }
+                        if queue.closed.load(Ordering::Relaxed) {
+                            break None;
+                        }
+
+                        state = match queue.condvar.wait(state) {
+                            Ok(state) => state,
+                            Err(_) => break None,
+                        };
+                    }
+                };
+
+                let Some((frame, dropped)) = next else {
+                    break;
+                };
+
+                let sent = tx.send(frame).is_ok();
+                if let Ok(mut state) = queue.state.lock() {
+                    state.forwarding = false;
+                    // 唤醒等待空位的发送端
+                    queue.condvar.notify_all();
+                }
+
+                if !sent {
+                    queue.close();
+                    break;
+                }
+                // 接收端跟不上时每帧都会丢弃，合并成一个事件
+                if dropped > 0 {
+                    queue.events.emit(RecorderEvent::FramesDropped(dropped));
+                }
+            }
+        });
+
+        (Arc::new(FrameQueueHandle(frame_queue)), rx)
+    }
+}
+
+/// 所有发送端释放后关闭队列，让转发线程退出
+#[derive(Debug)]
+pub(crate) struct FrameQueueHandle(Arc<FrameQueue>);
+
+impl Drop for FrameQueueHandle {
+    fn drop(&mut self) {
+        self.0.close();
+    }
+}
+
+/// Sends frames to a recorder's receiver, applying the channel capacity and backpressure policy
+#[derive(Debug, Clone)]
+pub(crate) enum FrameSender {
+    Unbounded(Sender<Frame>),
+    Block(Arc<FrameQueueHandle>),
+    DropOldest(Arc<FrameQueueHandle>),
+}
+
+impl FrameSender {
+    pub fn new(
+        capacity: Option<usize>,
+        backpressure: BackpressurePolicy,
+        events: &RecorderEvents,
+    ) -> (FrameSender, Receiver<Frame>) {
+        match (capacity, backpressure) {
+            (None, _) => {
+                let (tx, rx) = mpsc::channel();
+                (FrameSender::Unbounded(tx), rx)
+            }
+            (Some(capacity), BackpressurePolicy::Block) => {
+                let (handle, rx) = FrameQueue::spawn(capacity, events);
+                (FrameSender::Block(handle), rx)
+            }
+            (Some(capacity), BackpressurePolicy::DropOldest) => {
+                let (handle, rx) = FrameQueue::spawn(capacity, events);
+                (FrameSender::DropOldest(handle), rx)
+            }
+        }
+    }
+
+    /// Discards the frame once the recorder is closed or the receiver is dropped
+    pub fn send(&self, frame: Frame) -> XCapResult<()> {
+        match self {
+            FrameSender::Unbounded(tx) => {
+                // 接收端释放属于正常关闭，不是采集错误
+                if tx.send(frame).is_err() {
+                    log::debug!("Frame receiver is closed, discarding frame");
+                }
+
+                Ok(())
+            }
+            FrameSender::Block(handle) => {
+                let queue = &handle.0;
+                let mut state = queue.state.lock()?;
+
+                // 关闭后不再阻塞，丢弃帧让采集线程可以退出
+                loop {
+                    if queue.closed.load(Ordering::Relaxed) {
+                        return Ok(());
+                    }
+                    if state.len() < queue.capacity {
+                        break;
+                    }
+
+                    state = queue.condvar.wait(state)?;
+                }
+
+                state.frames.push_back(frame);
+                queue.condvar.notify_all();
+
+                Ok(())
+            }
+            FrameSender::DropOldest(handle) => {
+                let queue = &handle.0;
+                if queue.closed.load(Ordering::Relaxed) {
+                    return Ok(());
+                }
+
+                let mut state = queue.state.lock()?;
+                let mut frame = frame;
+                // 正在转发的帧无法丢弃
+                if state.len() >= queue.capacity
+                    && let Some(dropped) = state.frames.pop_front()
+                {
+                    // 被丢弃帧的变化区域交给下一个送达的帧
+                    match state.frames.front_mut() {
+                        Some(next) => next.merge_dirty_rects(&dropped),
+                        None => frame.merge_dirty_rects(&dropped),
+                    }
+                    state.dropped += 1;
+                }
+                state.frames.push_back(frame);
+                queue.condvar.notify_all();
+
+                Ok(())
+            }
+        }
+    }
+}
+
+impl FrameSender {
+    /// Stop blocking in `send`, queued frames can still be received
+    pub fn close(&self) {
+        match self {
+            FrameSender::Unbounded(_) => {}
+            FrameSender::Block(handle) | FrameSender::DropOldest(handle) => handle.0.close(),
+        }
+    }
+}
+
+/// A region of a monitor to record, in the monitor's coordinates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RecorderRegion {
+    pub rect: DirtyRect,
+    pub monitor_width: u32,
+    pub monitor_height: u32,
+}
+
+impl RecorderRegion {
+    /// The region in a `width`x`height` frame of the whole monitor, rounded outward
+    pub fn scaled(&self, width: u32, height: u32) -> DirtyRect {
+        let scale = |value: u32, size: u32, monitor_size: u32| {
+            (value as u64 * size as u64 / monitor_size.max(1) as u64) as u32
+        };
+        let scale_ceil = |value: u32, size: u32, monitor_size: u32| {
+            (value as u64 * size as u64).div_ceil(monitor_size.max(1) as u64) as u32
+        };
+
+        let x = scale(self.rect.x, width, self.monitor_width).min(width - 1);
+        let y = scale(self.rect.y, height, self.monitor_height).min(height - 1);
+        let right = scale_ceil(self.rect.x + self.rect.width, width, self.monitor_width)
+            .clamp(x + 1, width);
+        let bottom = scale_ceil(self.rect.y + self.rect.height, height, self.monitor_height)
+            .clamp(y + 1, height);
+
+        DirtyRect {
+            x,
+            y,
+            width: right - x,
+            height: bottom - y,
+        }
+    }
+
+    /// Crop a frame of the whole monitor, `None` when nothing changed in the region
+    /// except for the first frame
+    pub fn crop(&self, frame: &Frame) -> XCapResult<Option<Frame>> {
+        if frame.width == 0 || frame.height == 0 {
+            return Ok(None);
+        }
+
+        let rect = self.scaled(frame.width, frame.height);
+        let cropped = frame.crop(rect.x, rect.y, rect.width, rect.height)?;
+
+        // 第一帧没有可以参照的上一帧，整个区域都需要发送
+        if frame.sequence == 0 {
+            return Ok(Some(cropped.with_dirty_rects(vec![DirtyRect {
+                x: 0,
+                y: 0,
+                width: rect.width,
+                height: rect.height,
+            }])));
+        }
+        if cropped.dirty_rects.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(cropped))
+    }
+}
+
+/// Keeps frames at least `1 / fps` apart
+#[derive(Debug, Clone)]
+pub(crate) struct FrameInterval {
+    interval: Option<Duration>,
+    last: Option<Instant>,
+}
+
+impl FrameInterval {
+    pub fn new(fps: Option<f32>) -> FrameInterval {
+        FrameInterval {
+            interval: fps
+                .filter(|fps| *fps > 0.0)
+                .map(|fps| Duration::from_secs_f32(1.0 / fps)),
+            last: None,
+        }
+    }
+
+    /// The lower of two frame rates
+    pub fn from_options(options: &RecorderOptions) -> FrameInterval {
+        let fps = match (options.target_fps, options.max_fps) {
+            (Some(target_fps), Some(max_fps)) => Some(target_fps.min(max_fps)),
+            (target_fps, max_fps) => target_fps.or(max_fps),
+        };
+
+        FrameInterval::new(fps)
+    }
+
+    /// Sleep until the next frame is due, for backends that poll
+    pub fn wait(&mut self) {
+        if let (Some(interval), Some(last)) = (self.interval, self.last) {
+            let elapsed = last.elapsed();
+            if elapsed < interval {
+                thread::sleep(interval - elapsed);
+            }
+        }
+
+        self.last = Some(Instant::now());
+    }
+
+    /// How long until the next frame is due, `None` without a frame rate limit
+    pub fn remaining(&self) -> Option<Duration> {
+        let interval = self.interval?;
+
+        Some(match self.last {
+            Some(last) => interval.saturating_sub(last.elapsed()),
+            None => Duration::ZERO,
+        })
+    }
+
+    /// Whether a frame arriving now is due, for backends that are pushed frames
+    pub fn ready(&mut self) -> bool {
+        let now = Instant::now();
+
+        if let (Some(interval), Some(last)) = (self.interval, self.last)
+            && now.duration_since(last) < interval
+        {
+            return false;
+        }
+
+        self.last = Some(now);
+
+        true
+    }
+}
+
+/// The size and layout of the frames a recorder delivers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameFormat {
+    pub width: u32,
+    pub height: u32,
+    pub pixel_format: PixelFormat,
+    /// The negotiated frame rate, `None` when variable or unknown.
+    pub fps: Option<f32>,
+}
+
+/// Something that happened to a [`VideoRecorder`], see [`VideoRecorder::events`].
+#[derive(Debug, Clone)]
+pub enum RecorderEvent {
+    StateChanged(RecorderState),
+    /// The format of the delivered frames, after cropping and scaling. Sent before the first
+    /// frame and whenever the format changes.
+    FormatNegotiated(FrameFormat),
+    /// Capturing went wrong, the recorder keeps going unless its state changes to `Failed`.
+    /// An error that keeps happening is reported again at most every 5 seconds.
+    Error(Arc<XCapError>),
+    /// The compositor or the system ended the capture.
+    SourceClosed,
+    /// Frames discarded because of the frame rate limit or a full channel.
+    FramesDropped(u64),
+}
+
+/// Emits [`RecorderEvent::FormatNegotiated`] when the format of the delivered frames changes
+#[derive(Debug, Clone, Default)]
+pub(crate) struct FormatTracker {
+    last_format: Option<FrameFormat>,
+}
+
+impl FormatTracker {
+    /// Call with each frame right before it is sent
+    pub fn update(&mut self, frame: &Frame, fps: Option<f32>, events: &RecorderEvents) {
+        let format = FrameFormat {
+            width: frame.width,
+            height: frame.height,
+            pixel_format: frame.pixel_format,
+            fps,
+        };
+
+        if self.last_format != Some(format) {
+            self.last_format = Some(format);
+            events.emit(RecorderEvent::FormatNegotiated(format));
+        }
+    }
+}
+
+/// How long a repeated error is kept from flooding the event receivers
+const ERROR_REPEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Broadcasts events to every receiver from [`VideoRecorder::events`]
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RecorderEvents {
+    senders: Arc<Mutex<Vec<Sender<RecorderEvent>>>>,
+    /// The last error message and when it was emitted
+    last_error: Arc<Mutex<Option<(String, Instant)>>>,
+}
+
+impl RecorderEvents {
+    pub fn subscribe(&self) -> XCapResult<Receiver<RecorderEvent>> {
+        let (tx, rx) = mpsc::channel();
+        self.senders.lock()?.push(tx);
+
+        Ok(rx)
+    }
+
+    pub fn emit(&self, event: RecorderEvent) {
+        // 状态变化后同样的错误需要重新报告
+        if let RecorderEvent::StateChanged(_) = event
+            && let Ok(mut last_error) = self.last_error.lock()
+        {
+            *last_error = None;
+        }
+
+        if let Ok(mut senders) = self.senders.lock() {
+            senders.retain(|sender| sender.send(event.clone()).is_ok());
+        }
+    }
+
+    /// Emit an error, unless the same error was emitted less than 5 seconds ago
+    pub fn error(&self, err: XCapError) {
+        let message = err.to_string();
+
+        if let Ok(mut last_error) = self.last_error.lock() {
+            // 采集持续失败时每次循环都会出错，接收端不读取时会无限增长
+            if let Some((last_message, emitted_at)) = &*last_error
+                && *last_message == message
+                && emitted_at.elapsed() < ERROR_REPEAT_INTERVAL
+            {
+                return;
+            }
+
+            *last_error = Some((message, Instant::now()));
+        }
+
+        self.emit(RecorderEvent::Error(Arc::new(err)));
+    }
+}
+
+/// The state of a [`VideoRecorder`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecorderState {
+    /// Created but not started yet.
+    Idle,
+    Running,
+    /// Stopped, and can be started again.
+    Paused,
+    /// Capturing stopped on an error.
+    Failed,
+    Closed,
+}
+
+/// Tracks a recorder's state and its capture thread
+#[derive(Debug, Clone)]
+pub(crate) struct RecorderLifecycle {
+    state: Arc<Mutex<RecorderState>>,
+    state_changed: Arc<Condvar>,
+    thread: Arc<Mutex<Option<JoinHandle<()>>>>,
+    events: RecorderEvents,
+}
+
+impl RecorderLifecycle {
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(RecorderState::Idle)),
+            state_changed: Arc::new(Condvar::new()),
+            thread: Arc::new(Mutex::new(None)),
+            events: RecorderEvents::default(),
+        }
+    }
+
+    pub fn events(&self) -> &RecorderEvents {
+        &self.events
+    }
+
+    fn set_state(&self, state: &mut RecorderState, new_state: RecorderState) {
+        if *state != new_state {
+            *state = new_state;
+            self.state_changed.notify_all();
+            self.events.emit(RecorderEvent::StateChanged(new_state));
+        }
+    }
+
+    pub fn state(&self) -> XCapResult<RecorderState> {
+        Ok(*self.state.lock()?)
+    }
+
+    /// Block while the recorder is idle or paused, returns the state it moved to
+    pub fn wait_while_paused(&self) -> XCapResult<RecorderState> {
+        let mut state = self.state.lock()?;
+        while matches!(*state, RecorderState::Idle | RecorderState::Paused) {
+            state = self.state_changed.wait(state)?;
+        }
+
+        Ok(*state)
+    }
+
+    /// Run the capture thread, moving to `Failed` if it returns an error
+    pub fn spawn<F>(&self, f: F) -> XCapResult<()>
+    where
+        F: FnOnce() -> XCapResult<()> + Send + 'static,
+    {
+        let lifecycle = self.clone();
+        let handle = thread::spawn(move || {
+            if let Err(err) = f() {
+                log::error!("Recorder thread failed: {err:?}");
+                lifecycle.events.error(err);
+                lifecycle.fail();
+            }
+        });
+
+        *self.thread.lock()? = Some(handle);
+
+        Ok(())
+    }
+
+    pub fn fail(&self) {
+        if let Ok(mut state) = self.state.lock()
+            && *state != RecorderState::Closed
+        {
+            self.set_state(&mut state, RecorderState::Failed);
+        }
+    }
+
+    /// Returns false if the recorder was already running
+    pub fn start(&self) -> XCapResult<bool> {
+        let mut state = self.state.lock()?;
+
+        match *state {
+            RecorderState::Running => Ok(false),
+            RecorderState::Failed => Err(XCapError::new("Recorder has failed")),
+            RecorderState::Closed => Err(XCapError::new("Recorder is closed")),
+            RecorderState::Idle | RecorderState::Paused => {
+                self.set_state(&mut state, RecorderState::Running);
+                Ok(true)
+            }
+        }
+    }
+
+    pub fn stop(&self) -> XCapResult<()> {
+        let mut state = self.state.lock()?;
+        if *state == RecorderState::Running {
+            self.set_state(&mut state, RecorderState::Paused);
+        }
+
+        Ok(())
+    }
+
+    /// Returns false if the recorder was already closed
+    pub fn close(&self) -> XCapResult<bool> {
+        let mut state = self.state.lock()?;
+        if *state == RecorderState::Closed {
+            return Ok(false);
+        }
+        self.set_state(&mut state, RecorderState::Closed);
+
+        Ok(true)
+    }
+
+    /// Wait for the capture thread to exit
+    pub fn join(&self) -> XCapResult<()> {
+        let handle = self.thread.lock()?.take();
+
+        if let Some(handle) = handle
+            && handle.thread().id() != thread::current().id()
+        {
+            handle
+                .join()
+                .map_err(|_| XCapError::new("Recorder thread panicked"))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// 最后一个 VideoRecorder 释放时关闭录制
+#[derive(Debug)]
+struct VideoRecorderInner(ImplVideoRecorder);
+
+impl Drop for VideoRecorderInner {
+    fn drop(&mut self) {
+        if let Err(err) = self.0.close() {
+            log::error!("Failed to close recorder: {err:?}");
+        }
+    }
+}
+
+/// Records a monitor, closed when the last clone is dropped.
 #[derive(Debug, Clone)]
 pub struct VideoRecorder {
-    impl_video_recorder: ImplVideoRecorder,
+    inner: Arc<VideoRecorderInner>,
 }
 
 impl VideoRecorder {
     pub(crate) fn new(impl_video_recorder: ImplVideoRecorder) -> VideoRecorder {
         VideoRecorder {
-            impl_video_recorder,
+            inner: Arc::new(VideoRecorderInner(impl_video_recorder)),
         }
     }
 }
 
 impl VideoRecorder {
     pub fn start(&self) -> XCapResult<()> {
-        self.impl_video_recorder.start()
+        self.inner.0.start()
     }
     pub fn stop(&self) -> XCapResult<()> {
-        self.impl_video_recorder.stop()
+        self.inner.0.stop()
+    }
+    /// Stop capturing and release the capture resources, the receiver gets no more frames.
+    pub fn close(&self) -> XCapResult<()> {
+        self.inner.0.close()
+    }
+    pub fn state(&self) -> XCapResult<RecorderState> {
+        self.inner.0.state()
+    }
+    /// A receiver of the events emitted from now on, each call returns a new one.
+    pub fn events(&self) -> XCapResult<Receiver<RecorderEvent>> {
+        self.inner.0.events()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_padded_bgrx_to_rgba_image() {
+        let raw = vec![
+            3, 2, 1, 0, 6, 5, 4, 0, 0xAA, 0xAA, //
+            9, 8, 7, 0, 12, 11, 10, 0,
+        ];
+        let frame = Frame::new(2, 2, raw)
+            .with_pixel_format(PixelFormat::Bgrx)
+            .with_stride(10);
+
+        assert_eq!(frame.row(1), Some(&[9, 8, 7, 0, 12, 11, 10, 0][..]));
+
+        let rgba_image = frame.to_rgba_image().unwrap();
+        assert_eq!(
+            rgba_image.as_raw(),
+            &[1, 2, 3, 255, 4, 5, 6, 255, 7, 8, 9, 255, 10, 11, 12, 255]
+        );
+
+        let dynamic_image = frame.to_dynamic_image().unwrap();
+        assert!(matches!(dynamic_image, DynamicImage::ImageRgb8(_)));
+    }
+
+    #[test]
+    fn test_repeated_errors() {
+        let events = RecorderEvents::default();
+        let receiver = events.subscribe().unwrap();
+
+        for _ in 0..3 {
+            events.error(XCapError::new("capture failed"));
+        }
+        events.error(XCapError::new("monitor gone"));
+        events.emit(RecorderEvent::StateChanged(RecorderState::Paused));
+        events.error(XCapError::new("monitor gone"));
+
+        let errors: Vec<String> = receiver
+            .try_iter()
+            .filter_map(|event| match event {
+                RecorderEvent::Error(err) => Some(err.to_string()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(errors, ["capture failed", "monitor gone", "monitor gone"]);
+    }
+
+    #[test]
+    fn test_block_frame_sender() {
+        let events = RecorderEvents::default();
+        let (sender, receiver) = FrameSender::new(Some(0), BackpressurePolicy::Block, &events);
+
+        let send_sender = sender.clone();
+        let send_thread = thread::spawn(move || {
+            for sequence in 0..3 {
+                send_sender
+                    .send(Frame::new(1, 1, vec![0; 4]).with_sequence(sequence))
+                    .unwrap();
+            }
+        });
+
+        // 轮询的接收端也能收到帧
+        let mut sequences = Vec::new();
+        while sequences.len() < 3 {
+            match receiver.try_recv() {
+                Ok(frame) => sequences.push(frame.sequence),
+                Err(_) => thread::sleep(Duration::from_millis(1)),
+            }
+        }
+        send_thread.join().unwrap();
+        assert_eq!(sequences, [0, 1, 2]);
+
+        // 关闭后 send 不再阻塞
+        sender.send(Frame::new(1, 1, vec![0; 4])).unwrap();
+        sender.close();
+        sender.send(Frame::new(1, 1, vec![0; 4])).unwrap();
+    }
+
+    #[test]
+    fn test_close_without_error() {
+        let lifecycle = RecorderLifecycle::new();
+        let events = lifecycle.events().subscribe().unwrap();
+        let (sender, receiver) =
+            FrameSender::new(Some(1), BackpressurePolicy::Block, lifecycle.events());
+
+        // 和录制线程一样发送到关闭为止，队列满时阻塞在 send 中
+        let thread_sender = sender.clone();
+        let thread_lifecycle = lifecycle.clone();
+        lifecycle
+            .spawn(move || {
+                while thread_lifecycle.state()? != RecorderState::Closed {
+                    thread_sender.send(Frame::new(1, 1, vec![0; 4]))?;
+                }
+
+                Ok(())
+            })
+            .unwrap();
+        lifecycle.start().unwrap();
+        receiver.recv().unwrap();
+
+        lifecycle.close().unwrap();
+        sender.close();
+        lifecycle.join().unwrap();
+        drop(receiver);
+
+        assert!(
+            !events
+                .try_iter()
+                .any(|event| matches!(event, RecorderEvent::Error(_)))
+        );
+        assert_eq!(lifecycle.state().unwrap(), RecorderState::Closed);
+    }
+
+    #[test]
+    fn test_block_counts_forwarded_frame() {
+        let events = RecorderEvents::default();
+        let (sender, receiver) = FrameSender::new(Some(1), BackpressurePolicy::Block, &events);
+
+        let (sent_tx, sent_rx) = mpsc::channel();
+        let send_thread = thread::spawn(move || {
+            for sequence in 0..2 {
+                sender
+                    .send(Frame::new(1, 1, vec![0; 4]).with_sequence(sequence))
+                    .unwrap();
+                sent_tx.send(sequence).unwrap();
+            }
+        });
+
+        // 第一帧在转发线程中等待接收，第二帧要等它被取走才能发送
+        assert_eq!(sent_rx.recv().unwrap(), 0);
+        thread::sleep(Duration::from_millis(50));
+        assert!(sent_rx.try_recv().is_err());
+
+        assert_eq!(receiver.recv().unwrap().sequence, 0);
+        assert_eq!(sent_rx.recv().unwrap(), 1);
+        assert_eq!(receiver.recv().unwrap().sequence, 1);
+        send_thread.join().unwrap();
+    }
+
+    #[test]
+    fn test_drop_oldest_frame_sender() {
+        let events = RecorderEvents::default();
+        let dropped_events = events.subscribe().unwrap();
+        let (sender, receiver) = FrameSender::new(Some(2), BackpressurePolicy::DropOldest, &events);
+
+        for sequence in 0..5 {
+            let dirty_rect = DirtyRect {
+                x: sequence as u32,
+                y: 0,
+                width: 1,
+                height: 1,
+            };
+            sender
+                .send(
+                    Frame::new(5, 1, vec![0; 20])
+                        .with_dirty_rects(vec![dirty_rect])
+                        .with_sequence(sequence),
+                )
+                .unwrap();
+        }
+
+        drop(sender);
+
+        // 转发线程取走的帧也占用容量，最多保留两帧
+        let frames: Vec<Frame> = receiver.iter().collect();
+        let sequences: Vec<u64> = frames.iter().map(|frame| frame.sequence).collect();
+
+        // 被丢弃帧的变化区域没有丢失
+        let mut dirty_xs: Vec<u32> = frames
+            .iter()
+            .flat_map(|frame| frame.dirty_rects.iter().map(|dirty_rect| dirty_rect.x))
+            .collect();
+        dirty_xs.sort();
+        assert_eq!(dirty_xs, [0, 1, 2, 3, 4]);
+        assert!(sequences.len() <= 2);
+        assert_eq!(sequences.last(), Some(&4));
+        assert!(sequences.windows(2).all(|window| window[0] < window[1]));
+
+        // 丢帧事件随送达的帧合并发送
+        let dropped: Vec<u64> = dropped_events
+            .try_iter()
+            .filter_map(|event| match event {
+                RecorderEvent::FramesDropped(count) => Some(count),
+                _ => None,
+            })
+            .collect();
+        assert!(dropped.len() < sequences.len());
+        assert_eq!(dropped.iter().sum::<u64>() as usize + sequences.len(), 5);
+    }
+
+    #[test]
+    fn test_region_crop() {
+        // 2 倍缩放的显示器，每行有 8 字节填充
+        let raw: Vec<u8> = (0..4 * 24).map(|i| i as u8).collect();
+        let frame = Frame::new(4, 4, raw)
+            .with_pixel_format(PixelFormat::Bgrx)
+            .with_stride(24)
+            .with_dirty_rects(vec![DirtyRect {
+                x: 2,
+                y: 2,
+                width: 2,
+                height: 1,
+            }])
+            .with_sequence(1);
+
+        let region = RecorderRegion {
+            rect: DirtyRect {
+                x: 1,
+                y: 1,
+                width: 1,
+                height: 1,
+            },
+            monitor_width: 2,
+            monitor_height: 2,
+        };
+        let cropped = region.crop(&frame).unwrap().unwrap();
+        assert_eq!((cropped.width, cropped.height, cropped.stride), (2, 2, 8));
+        assert_eq!(&cropped.raw[..8], &frame.raw[56..64]);
+        assert_eq!(&cropped.raw[8..], &frame.raw[80..88]);
+        assert_eq!(
+            cropped.dirty_rects,
+            vec![DirtyRect {
+                x: 0,
+                y: 0,
+                width: 2,
+                height: 1,
+            }]
+        );
+
+        let unchanged = frame.with_dirty_rects(vec![DirtyRect {
+            x: 0,
+            y: 0,
+            width: 2,
+            height: 2,
+        }]);
+        assert!(region.crop(&unchanged).unwrap().is_none());
+
+        // 第一帧即使区域内没有变化也要发送
+        let first = region.crop(&unchanged.with_sequence(0)).unwrap().unwrap();
+        assert_eq!(
+            first.dirty_rects,
+            vec![DirtyRect {
+                x: 0,
+                y: 0,
+                width: 2,
+                height: 2,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_crop_unchanged() {
+        let frame = Frame::new(4, 2, vec![0; 32]).with_dirty_rects(vec![DirtyRect {
+            x: 3,
+            y: 0,
+            width: 1,
+            height: 2,
+        }]);
+
+        let cropped = frame.crop(0, 0, 2, 2).unwrap();
+        assert_eq!((cropped.width, cropped.height), (2, 2));
+        assert!(cropped.dirty_rects.is_empty());
+
+        let cropped = frame.crop(2, 1, 2, 1).unwrap();
+        assert_eq!(
+            cropped.dirty_rects,
+            vec![DirtyRect {
+                x: 1,
+                y: 0,
+                width: 1,
+                height: 1,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_resize_bgrx() {
+        let frame = Frame::new(2, 2, [3, 2, 1, 0].repeat(4))
+            .with_pixel_format(PixelFormat::Bgrx)
+            .with_dirty_rects(vec![DirtyRect {
+                x: 1,
+                y: 1,
+                width: 1,
+                height: 1,
+            }]);
+
+        let resized = frame.resize(4, 4).unwrap();
+        assert_eq!(resized.pixel_format, PixelFormat::Bgrx);
+        assert_eq!(resized.raw, [3, 2, 1, 0].repeat(16));
+        assert_eq!(
+            resized.dirty_rects,
+            vec![DirtyRect {
+                x: 2,
+                y: 2,
+                width: 2,
+                height: 2,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_planes_too_short() {
+        let frame = Frame::new(2, 2, vec![0; 8]);
+
+        assert!(frame.planes().is_err());
+        assert!(frame.to_rgba_image().is_err());
     }
 }
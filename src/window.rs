@@ -1,6 +1,50 @@
+use std::sync::mpsc::Receiver;
+
 use image::RgbaImage;
 
-use crate::{Monitor, error::XCapResult, platform::impl_window::ImplWindow};
+use crate::{
+    Monitor, VideoRecorder,
+    error::XCapResult,
+    platform::impl_window::ImplWindow,
+    video_recorder::{Frame, RecorderOptions},
+};
+
+/// Extents of the frame drawn around a window, in pixels.
+///
+/// Positive values are server-side decorations (title bar, borders) drawn
+/// outside the window, negative values are client-side shadows drawn inside it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameExtents {
+    pub left: i32,
+    pub right: i32,
+    pub top: i32,
+    pub bottom: i32,
+}
+
+/// Options for [`Window::capture_image_with_options`].
+///
+/// Only X11 supports them, other platforms capture the window as [`Window::capture_image`]
+/// does whatever the options are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WindowCaptureOptions {
+    /// Include the server-side decorations drawn by the window manager.
+    pub include_decorations: bool,
+    /// Include the client-side shadows drawn by the application.
+    pub include_shadow: bool,
+    /// Keep the alpha channel of windows using a 32-bit ARGB visual, un-premultiplied.
+    /// Otherwise the image is fully opaque.
+    pub preserve_alpha: bool,
+}
+
+impl Default for WindowCaptureOptions {
+    fn default() -> Self {
+        WindowCaptureOptions {
+            include_decorations: false,
+            include_shadow: true,
+            preserve_alpha: false,
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Window {
@@ -34,6 +78,11 @@ impl Window {
     pub fn pid(&self) -> XCapResult<u32> {
         self.impl_window.pid()
     }
+    /// The window process details, read from `/proc`, only on Linux
+    #[cfg(target_os = "linux")]
+    pub fn process(&self) -> XCapResult<crate::ProcessInfo> {
+        self.impl_window.process()
+    }
     /// The window app name
     pub fn app_name(&self) -> XCapResult<String> {
         self.impl_window.app_name()
@@ -78,10 +127,40 @@ impl Window {
     pub fn is_focused(&self) -> XCapResult<bool> {
         self.impl_window.is_focused()
     }
+    /// The window frame extents.
+    pub fn frame_extents(&self) -> XCapResult<FrameExtents> {
+        self.impl_window.frame_extents()
+    }
 }
 
 impl Window {
     pub fn capture_image(&self) -> XCapResult<RgbaImage> {
         self.impl_window.capture_image()
     }
+
+    /// Capture image of the window, with or without its decorations and shadows.
+    /// The options are ignored everywhere but on X11.
+    pub fn capture_image_with_options(
+        &self,
+        options: &WindowCaptureOptions,
+    ) -> XCapResult<RgbaImage> {
+        self.impl_window.capture_image_with_options(options)
+    }
+
+    /// Record the window, frames change size when the window is resized.
+    /// On Wayland the user picks the window to record.
+    pub fn video_recorder(&self) -> XCapResult<(VideoRecorder, Receiver<Frame>)> {
+        self.video_recorder_with_options(&RecorderOptions::default())
+    }
+
+    pub fn video_recorder_with_options(
+        &self,
+        options: &RecorderOptions,
+    ) -> XCapResult<(VideoRecorder, Receiver<Frame>)> {
+        options.validate()?;
+
+        let (impl_video_recorder, sx) = self.impl_window.video_recorder(options)?;
+
+        Ok((VideoRecorder::new(impl_video_recorder), sx))
+    }
 }
@@ -0,0 +1,185 @@
+use std::{
+    sync::{Arc, mpsc::Receiver},
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    XCapError, XCapResult,
+    platform::impl_window::ImplWindow,
+    video_recorder::{
+        FormatTracker, Frame, FrameInterval, FrameSender, RecorderEvent, RecorderLifecycle,
+        RecorderOptions, RecorderState, RecorderWaker,
+    },
+};
+
+/// The window is gone when it is no longer listed
+fn is_window_closed(window: &ImplWindow) -> bool {
+    let (Ok(id), Ok(windows)) = (window.id(), ImplWindow::all()) else {
+        return false;
+    };
+
+    !windows
+        .iter()
+        .any(|impl_window| impl_window.id().is_ok_and(|window_id| window_id == id))
+}
+
+/// Records a window by capturing it repeatedly, frames are always RGBA
+#[derive(Debug, Clone)]
+pub(crate) struct WindowVideoRecorder {
+    window: ImplWindow,
+    options: RecorderOptions,
+    sender: FrameSender,
+    lifecycle: RecorderLifecycle,
+    recorder_waker: Arc<RecorderWaker>,
+}
+
+impl WindowVideoRecorder {
+    pub fn new(
+        window: ImplWindow,
+        options: RecorderOptions,
+    ) -> XCapResult<(Self, Receiver<Frame>)> {
+        if options.native_pixel_format {
+            return Err(XCapError::new(
+                "Native pixel format is not supported when recording a window",
+            ));
+        }
+
+        let lifecycle = RecorderLifecycle::new();
+        let (sender, receiver) = FrameSender::new(
+            options.channel_capacity,
+            options.backpressure,
+            lifecycle.events(),
+        );
+        let recorder = Self {
+            window,
+            options,
+            sender,
+            lifecycle,
+            recorder_waker: Arc::new(RecorderWaker::new()),
+        };
+
+        recorder.on_frame()?;
+
+        Ok((recorder, receiver))
+    }
+
+    fn on_frame(&self) -> XCapResult<()> {
+        let window = self.window.clone();
+        let options = self.options.clone();
+        let sender = self.sender.clone();
+        let lifecycle = self.lifecycle.clone();
+        let recorder_waker = self.recorder_waker.clone();
+
+        self.lifecycle.spawn(move || {
+            let events = lifecycle.events();
+            let mut last_raw: Option<Vec<u8>> = None;
+            let mut last_size: Option<(u32, u32)> = None;
+            let mut format_tracker = FormatTracker::default();
+            let mut sequence = 0;
+            let mut frame_interval = FrameInterval::from_options(&options);
+
+            loop {
+                recorder_waker.wait()?;
+
+                match lifecycle.state()? {
+                    RecorderState::Running => {}
+                    RecorderState::Closed => break Ok(()),
+                    _ => {
+                        // 暂停时等待 recorder_waker 重新唤醒
+                        thread::sleep(Duration::from_millis(1));
+                        continue;
+                    }
+                }
+
+                frame_interval.wait();
+
+                // 最小化的窗口截图没有内容，等待窗口恢复
+                if window.is_minimized().unwrap_or(false) {
+                    thread::sleep(Duration::from_millis(50));
+                    continue;
+                }
+
+                let image = match window.capture_image() {
+                    Ok(image) => image,
+                    Err(err) => {
+                        if is_window_closed(&window) {
+                            events.emit(RecorderEvent::SourceClosed);
+                            lifecycle.fail();
+                            break Ok(());
+                        }
+
+                        log::error!("Failed to capture window: {err:?}");
+                        events.error(err);
+                        thread::sleep(Duration::from_millis(10));
+                        continue;
+                    }
+                };
+
+                // 窗口大小改变时，后续帧使用新的宽高
+                let size = (image.width(), image.height());
+                if last_size != Some(size) {
+                    last_size = Some(size);
+                    last_raw = None;
+                }
+
+                // 没有变化则不发送帧
+                if last_raw.as_deref() == Some(image.as_raw().as_slice()) {
+                    thread::sleep(Duration::from_millis(5));
+                    continue;
+                }
+
+                let raw = image.into_raw();
+                let frame = Frame::new(size.0, size.1, raw.clone()).with_sequence(sequence);
+                last_raw = Some(raw);
+
+                match options.scale_frame(frame) {
+                    Ok(frame) => {
+                        format_tracker.update(&frame, options.target_fps, events);
+                        sender.send(frame)?;
+                        sequence += 1;
+                    }
+                    Err(err) => {
+                        log::error!("Failed to scale frame: {err:?}");
+                        events.error(err);
+                    }
+                }
+            }
+        })
+    }
+
+    pub fn start(&self) -> XCapResult<()> {
+        if self.lifecycle.start()? {
+            self.recorder_waker.wake()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn stop(&self) -> XCapResult<()> {
+        self.lifecycle.stop()?;
+        self.recorder_waker.sleep()?;
+
+        Ok(())
+    }
+
+    pub fn close(&self) -> XCapResult<()> {
+        if !self.lifecycle.close()? {
+            return Ok(());
+        }
+
+        self.sender.close();
+        // 唤醒暂停中的线程，让它看到关闭状态后退出
+        self.recorder_waker.wake()?;
+
+        self.lifecycle.join()
+    }
+
+    pub fn state(&self) -> XCapResult<RecorderState> {
+        self.lifecycle.state()
+    }
+
+    pub fn events(&self) -> XCapResult<Receiver<RecorderEvent>> {
+        self.lifecycle.events().subscribe()
+    }
+}
@@ -20,8 +20,9 @@ use windows::{
 };
 
 use crate::{
+    change_waiter::{ChangeWaiter, PollingWaiter},
     error::{XCapError, XCapResult},
-    video_recorder::Frame,
+    video_recorder::{DirtyRect, Frame, RecorderOptions, RecorderRegion},
 };
 
 use super::{
@@ -279,7 +280,7 @@ impl ImplMonitor {
         capture_monitor(x, y, width as i32, height as i32)
     }
 
-    pub fn capture_region(&self, x: u32, y: u32, width: u32, height: u32) -> XCapResult<RgbaImage> {
+    fn check_region(&self, x: u32, y: u32, width: u32, height: u32) -> XCapResult<()> {
         // Validate region bounds
         let monitor_x = self.x()?;
         let monitor_y = self.y()?;
@@ -296,14 +297,53 @@ impl ImplMonitor {
             )));
         }
 
+        Ok(())
+    }
+
+    pub fn capture_region(&self, x: u32, y: u32, width: u32, height: u32) -> XCapResult<RgbaImage> {
+        self.check_region(x, y, width, height)?;
+
         // Calculate absolute coordinates
-        let abs_x = monitor_x + x as i32;
-        let abs_y = monitor_y + y as i32;
+        let abs_x = self.x()? + x as i32;
+        let abs_y = self.y()? + y as i32;
 
         capture_monitor(abs_x, abs_y, width as i32, height as i32)
     }
 
-    pub fn video_recorder(&self) -> XCapResult<(ImplVideoRecorder, Receiver<Frame>)> {
-        ImplVideoRecorder::new(self.h_monitor)
+    pub fn change_waiter(&self, region: &DirtyRect) -> XCapResult<Box<dyn ChangeWaiter>> {
+        self.check_region(region.x, region.y, region.width, region.height)?;
+
+        Ok(Box::new(PollingWaiter::default()))
+    }
+
+    pub fn video_recorder(
+        &self,
+        options: &RecorderOptions,
+    ) -> XCapResult<(ImplVideoRecorder, Receiver<Frame>)> {
+        ImplVideoRecorder::new(self.h_monitor, None, options)
+    }
+
+    pub fn region_video_recorder(
+        &self,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        options: &RecorderOptions,
+    ) -> XCapResult<(ImplVideoRecorder, Receiver<Frame>)> {
+        self.check_region(x, y, width, height)?;
+
+        let region = RecorderRegion {
+            rect: DirtyRect {
+                x,
+                y,
+                width,
+                height,
+            },
+            monitor_width: self.width()?,
+            monitor_height: self.height()?,
+        };
+
+        ImplVideoRecorder::new(self.h_monitor, Some(region), options)
     }
 }
@@ -1,10 +1,8 @@
 use std::{
     slice,
-    sync::{
-        Arc,
-        mpsc::{Receiver, SyncSender, sync_channel},
-    },
+    sync::{Arc, mpsc::Receiver},
     thread,
+    time::Duration,
 };
 
 use windows::{
@@ -19,8 +17,8 @@ use windows::{
                 ID3D11Device, ID3D11DeviceContext, ID3D11Resource, ID3D11Texture2D,
             },
             Dxgi::{
-                DXGI_ERROR_WAIT_TIMEOUT, DXGI_OUTDUPL_FRAME_INFO, IDXGIDevice, IDXGIOutput1,
-                IDXGIOutputDuplication, IDXGIResource,
+                DXGI_ERROR_ACCESS_LOST, DXGI_ERROR_WAIT_TIMEOUT, DXGI_OUTDUPL_FRAME_INFO,
+                IDXGIDevice, IDXGIOutput1, IDXGIOutputDuplication, IDXGIResource,
             },
             Gdi::HMONITOR,
         },
@@ -30,15 +28,21 @@ use windows::{
 
 use crate::{
     XCapError, XCapResult,
-    video_recorder::{Frame, RecorderWaker},
+    pixel_format::PixelFormat,
+    video_recorder::{
+        FormatTracker, Frame, FrameInterval, FrameSender, RecorderEvent, RecorderLifecycle,
+        RecorderOptions, RecorderRegion, RecorderState, RecorderWaker,
+    },
+    window_video_recorder::WindowVideoRecorder,
 };
 
-use super::utils::bgra_to_rgba;
+use super::{impl_window::ImplWindow, utils::bgra_to_rgba};
 
 pub fn texture_to_frame(
     d3d_device: &ID3D11Device,
     d3d_context: &ID3D11DeviceContext,
     source_texture: ID3D11Texture2D,
+    native_pixel_format: bool,
 ) -> XCapResult<Frame> {
     unsafe {
         let mut source_desc = D3D11_TEXTURE2D_DESC::default();
@@ -67,32 +71,51 @@ pub fn texture_to_frame(
         )?;
 
         // Get a slice of bytes
-        let bgra = slice::from_raw_parts(
-            mapped.pData.cast(),
+        let data = slice::from_raw_parts(
+            mapped.pData.cast::<u8>(),
             (source_desc.Height * mapped.RowPitch) as usize,
         );
 
+        // 去掉每行末尾的填充
+        let row_size = (source_desc.Width * 4) as usize;
+        let mut bgra = Vec::with_capacity(row_size * source_desc.Height as usize);
+        for row in data.chunks_exact(mapped.RowPitch as usize) {
+            bgra.extend_from_slice(&row[..row_size]);
+        }
+
         d3d_context.Unmap(Some(&resource), 0);
 
+        if native_pixel_format {
+            return Ok(Frame::new(source_desc.Width, source_desc.Height, bgra)
+                .with_pixel_format(PixelFormat::Bgra));
+        }
+
         Ok(Frame::new(
             source_desc.Width,
             source_desc.Height,
-            bgra_to_rgba(bgra.to_owned()),
+            bgra_to_rgba(bgra),
         ))
     }
 }
 
 #[derive(Debug, Clone)]
-pub struct ImplVideoRecorder {
+pub struct MonitorVideoRecorder {
     d3d_device: ID3D11Device,
     d3d_context: ID3D11DeviceContext,
     duplication: IDXGIOutputDuplication,
     recorder_waker: Arc<RecorderWaker>,
-    tx: SyncSender<Frame>,
+    tx: FrameSender,
+    region: Option<RecorderRegion>,
+    options: RecorderOptions,
+    lifecycle: RecorderLifecycle,
 }
 
-impl ImplVideoRecorder {
-    pub fn new(h_monitor: HMONITOR) -> XCapResult<(Self, Receiver<Frame>)> {
+impl MonitorVideoRecorder {
+    pub fn new(
+        h_monitor: HMONITOR,
+        region: Option<RecorderRegion>,
+        options: &RecorderOptions,
+    ) -> XCapResult<(Self, Receiver<Frame>)> {
         unsafe {
             let mut d3d_device = None;
             D3D11CreateDevice(
@@ -123,13 +146,21 @@ impl ImplVideoRecorder {
                 let duplication = output1.DuplicateOutput(&dxgi_device)?;
 
                 if output_desc.Monitor == h_monitor {
-                    let (tx, sx) = sync_channel(0);
+                    let lifecycle = RecorderLifecycle::new();
+                    let (tx, sx) = FrameSender::new(
+                        options.channel_capacity.or(Some(0)),
+                        options.backpressure,
+                        lifecycle.events(),
+                    );
                     let s = Self {
                         d3d_device,
                         d3d_context,
                         duplication,
                         recorder_waker: Arc::new(RecorderWaker::new()),
                         tx,
+                        region,
+                        options: options.clone(),
+                        lifecycle,
                     };
                     s.on_frame()?;
                     return Ok((s, sx));
@@ -144,11 +175,29 @@ impl ImplVideoRecorder {
         let d3d_context = self.d3d_context.clone();
         let recorder_waker = self.recorder_waker.clone();
         let tx = self.tx.clone();
+        let options = self.options.clone();
+        let region = self.region;
+        let lifecycle = self.lifecycle.clone();
+
+        self.lifecycle.spawn(move || {
+            let events = lifecycle.events();
+            let mut sequence = 0;
+            let mut frame_interval = FrameInterval::from_options(&options);
+            let mut format_tracker = FormatTracker::default();
 
-        thread::spawn(move || {
             loop {
                 recorder_waker.wait()?;
 
+                match lifecycle.state()? {
+                    RecorderState::Running => {}
+                    RecorderState::Closed => break Ok(()),
+                    _ => {
+                        // 暂停时等待 recorder_waker 重新唤醒
+                        thread::sleep(Duration::from_millis(1));
+                        continue;
+                    }
+                }
+
                 let mut frame_info = DXGI_OUTDUPL_FRAME_INFO::default();
                 let mut resource: Option<IDXGIResource> = None;
                 unsafe {
@@ -156,6 +205,10 @@ impl ImplVideoRecorder {
                         Err(err) => {
                             // 尝试释放当前帧，不然不能获取到下一帧数据
                             let _ = duplication.ReleaseFrame();
+                            // 桌面切换（如 UAC、锁屏）后复制接口失效
+                            if err.code() == DXGI_ERROR_ACCESS_LOST {
+                                events.emit(RecorderEvent::SourceClosed);
+                            }
                             if err.code() != DXGI_ERROR_WAIT_TIMEOUT {
                                 break Err::<(), XCapError>(XCapError::new(
                                     "DXGI_ERROR_UNSUPPORTED",
@@ -164,13 +217,45 @@ impl ImplVideoRecorder {
                         }
                         _ => {
                             // 如何确定 AcquireNextFrame 执行成功
-                            if frame_info.LastPresentTime != 0 {
+                            if frame_info.LastPresentTime != 0 && !frame_interval.ready() {
+                                events.emit(RecorderEvent::FramesDropped(1));
+                            } else if frame_info.LastPresentTime != 0 {
                                 let resource =
                                     resource.ok_or(XCapError::new("AcquireNextFrame failed"))?;
                                 let source_texture = resource.cast::<ID3D11Texture2D>()?;
-                                let frame =
-                                    texture_to_frame(&d3d_device, &d3d_context, source_texture)?;
-                                let _ = tx.send(frame);
+                                let frame = texture_to_frame(
+                                    &d3d_device,
+                                    &d3d_context,
+                                    source_texture,
+                                    options.native_pixel_format,
+                                )?
+                                .with_sequence(sequence);
+
+                                // 只录制区域时，先裁剪再缩放，出错时跳过这一帧
+                                let frame = match region.map(|region| region.crop(&frame)) {
+                                    None => Some(frame),
+                                    Some(Ok(frame)) => frame,
+                                    Some(Err(err)) => {
+                                        log::error!("Failed to crop frame: {err:?}");
+                                        events.error(err);
+                                        None
+                                    }
+                                };
+                                let frame = match frame.map(|frame| options.scale_frame(frame)) {
+                                    None => None,
+                                    Some(Ok(frame)) => Some(frame),
+                                    Some(Err(err)) => {
+                                        log::error!("Failed to scale frame: {err:?}");
+                                        events.error(err);
+                                        None
+                                    }
+                                };
+                                if let Some(frame) = frame {
+                                    format_tracker.update(&frame, options.target_fps, events);
+                                    if tx.send(frame).is_ok() {
+                                        sequence += 1;
+                                    }
+                                }
                             }
 
                             // 最后释放帧，不然获取不到当前帧的数据
@@ -179,18 +264,96 @@ impl ImplVideoRecorder {
                     }
                 }
             }
-        });
-
-        Ok(())
+        })
     }
     pub fn start(&self) -> XCapResult<()> {
-        self.recorder_waker.wake()?;
+        if self.lifecycle.start()? {
+            self.recorder_waker.wake()?;
+        }
 
         Ok(())
     }
     pub fn stop(&self) -> XCapResult<()> {
+        self.lifecycle.stop()?;
         self.recorder_waker.sleep()?;
 
         Ok(())
     }
+    pub fn close(&self) -> XCapResult<()> {
+        if !self.lifecycle.close()? {
+            return Ok(());
+        }
+
+        self.tx.close();
+        // 唤醒暂停中的线程，让它看到关闭状态后退出
+        self.recorder_waker.wake()?;
+
+        self.lifecycle.join()
+    }
+    pub fn state(&self) -> XCapResult<RecorderState> {
+        self.lifecycle.state()
+    }
+    pub fn events(&self) -> XCapResult<Receiver<RecorderEvent>> {
+        self.lifecycle.events().subscribe()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ImplVideoRecorder {
+    Monitor(MonitorVideoRecorder),
+    Window(WindowVideoRecorder),
+}
+
+impl ImplVideoRecorder {
+    pub fn new(
+        h_monitor: HMONITOR,
+        region: Option<RecorderRegion>,
+        options: &RecorderOptions,
+    ) -> XCapResult<(Self, Receiver<Frame>)> {
+        let (recorder, receiver) = MonitorVideoRecorder::new(h_monitor, region, options)?;
+        Ok((ImplVideoRecorder::Monitor(recorder), receiver))
+    }
+
+    pub fn new_window(
+        window: ImplWindow,
+        options: &RecorderOptions,
+    ) -> XCapResult<(Self, Receiver<Frame>)> {
+        let (recorder, receiver) = WindowVideoRecorder::new(window, options.clone())?;
+        Ok((ImplVideoRecorder::Window(recorder), receiver))
+    }
+
+    pub fn start(&self) -> XCapResult<()> {
+        match self {
+            ImplVideoRecorder::Monitor(recorder) => recorder.start(),
+            ImplVideoRecorder::Window(recorder) => recorder.start(),
+        }
+    }
+
+    pub fn stop(&self) -> XCapResult<()> {
+        match self {
+            ImplVideoRecorder::Monitor(recorder) => recorder.stop(),
+            ImplVideoRecorder::Window(recorder) => recorder.stop(),
+        }
+    }
+
+    pub fn close(&self) -> XCapResult<()> {
+        match self {
+            ImplVideoRecorder::Monitor(recorder) => recorder.close(),
+            ImplVideoRecorder::Window(recorder) => recorder.close(),
+        }
+    }
+
+    pub fn state(&self) -> XCapResult<RecorderState> {
+        match self {
+            ImplVideoRecorder::Monitor(recorder) => recorder.state(),
+            ImplVideoRecorder::Window(recorder) => recorder.state(),
+        }
+    }
+
+    pub fn events(&self) -> XCapResult<Receiver<RecorderEvent>> {
+        match self {
+            ImplVideoRecorder::Monitor(recorder) => recorder.events(),
+            ImplVideoRecorder::Window(recorder) => recorder.events(),
+        }
+    }
 }
@@ -1,5 +1,5 @@
 use core::slice;
-use std::{ffi::c_void, mem, ptr};
+use std::{ffi::c_void, mem, ptr, sync::mpsc::Receiver};
 
 use image::RgbaImage;
 use widestring::U16CString;
@@ -26,12 +26,19 @@ use windows::{
     core::{BOOL, HSTRING, PCWSTR},
 };
 
-use crate::error::XCapResult;
+use crate::{
+    error::XCapResult,
+    video_recorder::{Frame, RecorderOptions},
+    window::{FrameExtents, WindowCaptureOptions},
+};
 
 use super::{
     capture::capture_window,
     impl_monitor::ImplMonitor,
-    utils::{get_process_is_dpi_awareness, get_window_info, open_process, window_has_native_header},
+    impl_video_recorder::ImplVideoRecorder,
+    utils::{
+        get_process_is_dpi_awareness, get_window_info, open_process, window_has_native_header,
+    },
 };
 
 #[derive(Debug, Clone)]
@@ -420,6 +427,19 @@ impl ImplWindow {
         unsafe { Ok(GetForegroundWindow() == self.hwnd) }
     }
 
+    pub fn frame_extents(&self) -> XCapResult<FrameExtents> {
+        let window_info = get_window_info(self.hwnd)?;
+        let rc_client = window_info.rcClient;
+        let rc_window = window_info.rcWindow;
+
+        Ok(FrameExtents {
+            left: rc_client.left - rc_window.left,
+            right: rc_window.right - rc_client.right,
+            top: rc_client.top - rc_window.top,
+            bottom: rc_window.bottom - rc_client.bottom,
+        })
+    }
+
     pub fn capture_image(&self) -> XCapResult<RgbaImage> {
         // 在win10之后，不同窗口有不同的dpi，所以可能存在截图不全或者截图有较大空白，实际窗口没有填充满图片
         // 如果窗口不感知dpi，那么就不需要缩放，如果当前进程感知dpi，那么也不需要缩放
@@ -437,4 +457,19 @@ impl ImplWindow {
 
         capture_window(self.hwnd, scale_factor)
     }
+
+    /// The options only apply on X11
+    pub fn capture_image_with_options(
+        &self,
+        _options: &WindowCaptureOptions,
+    ) -> XCapResult<RgbaImage> {
+        self.capture_image()
+    }
+
+    pub fn video_recorder(
+        &self,
+        options: &RecorderOptions,
+    ) -> XCapResult<(ImplVideoRecorder, Receiver<Frame>)> {
+        ImplVideoRecorder::new_window(self.clone(), options)
+    }
 }